@@ -0,0 +1,144 @@
+//! Podcasting 2.0 chapters, published by feeds as a JSON document linked
+//! from `podcast:chapters`
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::PgError;
+use crate::http::HttpClient;
+
+/// The chapters document, keeping only what gets embedded
+#[derive(Debug, Deserialize)]
+struct ChaptersDocument {
+    chapters: Vec<JsonChapter>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonChapter {
+    start_time: f64,
+    #[serde(default)]
+    end_time: Option<f64>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    img: Option<String>,
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default = "default_toc")]
+    toc: bool,
+}
+
+fn default_toc() -> bool {
+    true
+}
+
+/// A chapter ready to embed in an audio file
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    pub start_ms: u32,
+    pub end_ms: u32,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    /// The chapter's image, as downloaded
+    pub image: Option<Vec<u8>>,
+    /// False for chapters that only carry metadata and shouldn't be listed
+    /// in the table of contents
+    pub toc: bool,
+}
+
+/// Where an episode's chapters document is saved: next to the episode, as
+/// `<episode>.chapters.json`
+pub fn sidecar_path(file_path: &Path) -> PathBuf {
+    file_path.with_extension("chapters.json")
+}
+
+/// Fetch an episode's chapters document, save it next to the downloaded file
+/// and return its chapters in order, with their images. `duration` is the
+/// episode's running time in seconds, used to end the last chapter.
+pub async fn fetch_chapters(
+    http: &HttpClient,
+    url: &str,
+    duration: Option<u64>,
+    file_path: &Path,
+) -> Result<Vec<Chapter>, PgError> {
+    let body = http.get_bytes(url).await?;
+    let document: ChaptersDocument =
+        serde_json::from_slice(&body).map_err(|e| PgError::parse("chapters JSON", e))?;
+
+    let sidecar = sidecar_path(file_path);
+    std::fs::write(&sidecar, &body).map_err(|e| PgError::filesystem("write chapters", &sidecar, e))?;
+
+    let mut json_chapters: Vec<JsonChapter> = document
+        .chapters
+        .into_iter()
+        .filter(|chapter| chapter.start_time.is_finite() && chapter.start_time >= 0.0)
+        .collect();
+    json_chapters.sort_by(|a, b| a.start_time.total_cmp(&b.start_time));
+
+    // Chapters often share one image, so each is only downloaded once
+    let mut images: HashMap<String, Option<Vec<u8>>> = HashMap::new();
+    for image_url in json_chapters.iter().filter_map(|chapter| chapter.img.as_deref()) {
+        if images.contains_key(image_url) {
+            continue;
+        }
+        let image = match http.get_bytes(image_url).await {
+            Ok(data) => Some(data),
+            Err(e) => {
+                tracing::warn!("Failed to download chapter image {}: {}", image_url, e);
+                None
+            }
+        };
+        images.insert(image_url.to_string(), image);
+    }
+
+    let episode_end = duration.map(|seconds| seconds.saturating_mul(1000).min(u32::MAX as u64) as u32);
+    let chapters = json_chapters
+        .iter()
+        .enumerate()
+        .map(|(i, chapter)| {
+            let start_ms = to_millis(chapter.start_time);
+            // Without an end time a chapter runs until the next one starts,
+            // and the last one until the end of the episode
+            let next_start = json_chapters[i + 1..]
+                .iter()
+                .map(|next| to_millis(next.start_time))
+                .find(|&next| next > start_ms);
+            let end_ms = chapter
+                .end_time
+                .filter(|end| end.is_finite())
+                .map(to_millis)
+                .or(next_start)
+                .or(episode_end)
+                .unwrap_or(start_ms)
+                .max(start_ms);
+
+            Chapter {
+                start_ms,
+                end_ms,
+                title: non_empty(&chapter.title),
+                url: non_empty(&chapter.url),
+                image: chapter
+                    .img
+                    .as_ref()
+                    .and_then(|image_url| images.get(image_url).cloned().flatten()),
+                toc: chapter.toc,
+            }
+        })
+        .collect();
+    Ok(chapters)
+}
+
+fn to_millis(seconds: f64) -> u32 {
+    (seconds.max(0.0) * 1000.0).round().min(u32::MAX as f64) as u32
+}
+
+fn non_empty(value: &Option<String>) -> Option<String> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
@@ -1,133 +1,575 @@
-use crate::config::{Config, PodcastConfig};
+use crate::chapters;
+use crate::config::{Backfill, Config, CoverFilename, EpisodeOrder, PodcastConfig, ProcessingSettings};
 use crate::download;
+use crate::error::{ErrorKind, ExitStatus, Failure, PgError};
 use crate::feed;
+use crate::filter::EpisodeFilter;
+use crate::http::HttpClient;
 use crate::image;
-use crate::state::State;
+use crate::output::{Output, OutputFormat};
+use crate::processing;
+use crate::replaygain;
+use crate::state::{EpisodeStatus, State};
 use crate::tagger;
+use crate::transcripts;
 use anyhow::{bail, Result};
-use chrono::Utc;
-use std::path::PathBuf;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use tracing::info;
 
-pub async fn download_all_podcasts(max_episodes: Option<usize>) -> Result<()> {
+pub async fn download_all_podcasts(
+    max_episodes: Option<usize>,
+    fail_fast: bool,
+    format: OutputFormat,
+) -> Result<ExitStatus> {
     info!("Starting podcast download");
+    let mut out = Output::new(format, "download");
 
     let config = Config::load()?;
-    let base_dir = config.base_dir.clone();
+    let _lock = State::lock()?;
     let mut state = State::load()?;
+    let http = HttpClient::new(&config.http).map_err(PgError::config)?;
 
-    let mut errors = Vec::new();
+    let mut failures = Vec::new();
+    let mut checked = 0;
+    let mut succeeded = 0;
+    let mut downloaded = 0;
 
-    for podcast in config.podcasts {
+    for podcast in &config.podcasts {
         if podcast.paused {
             info!("Skipping '{}' (paused)", podcast.name);
             continue;
         }
-        match download_podcast(&podcast, base_dir.as_deref(), &mut state, max_episodes).await {
-            Ok(count) => {
-                info!("Downloaded {} new episodes from {}", count, podcast.name);
+        checked += 1;
+        match download_podcast(podcast, &config, &http, &mut state, max_episodes, fail_fast, &mut out).await {
+            Ok(report) => {
+                info!("Downloaded {} new episodes from {}", report.downloaded, podcast.name);
+                if report.downloaded > 0 || report.failures.is_empty() {
+                    succeeded += 1;
+                }
+                downloaded += report.downloaded;
+                failures.extend(report.failures);
             }
             Err(e) => {
-                let error_msg = format!("Failed to process {}: {}", podcast.name, e);
-                tracing::error!("{}", error_msg);
-                errors.push(error_msg);
+                tracing::error!("Failed to process {}: {}", podcast.name, e);
+                let failure = Failure::new(&podcast.name, None, &e);
+                out.record("feed_failed", &failure)?;
+                failures.push(failure);
             }
         }
+
+        if fail_fast && !failures.is_empty() {
+            tracing::warn!("Stopping at the first error (--fail-fast)");
+            break;
+        }
     }
 
     // Save updated state
     state.save()?;
 
-    // Report errors
-    if !errors.is_empty() {
-        println!("\n⚠️  Errors encountered while processing feeds:");
-        for error in &errors {
-            println!("  - {}", error);
-        }
+    let status = ExitStatus::for_run(&failures, succeeded);
+    if out.is_table() {
+        print_failures(&failures);
     }
+    out.summary(&DownloadSummary {
+        podcasts: checked,
+        downloaded,
+        failed: failures.len(),
+        exit_code: status.code(),
+        failures: &failures,
+    })?;
+    out.finish()?;
 
     info!("Podcast download complete");
-    Ok(())
+    Ok(status)
+}
+
+/// Closing summary of `pg download`, `pg update-feed` and `pg backfill` in JSON output
+#[derive(Serialize)]
+struct DownloadSummary<'a> {
+    /// Podcasts checked, not counting paused ones
+    podcasts: usize,
+    downloaded: usize,
+    failed: usize,
+    exit_code: u8,
+    failures: &'a [Failure],
+}
+
+/// What happened to one episode during a download, in JSON output
+#[derive(Serialize)]
+struct EpisodeEvent<'a> {
+    podcast: &'a str,
+    id: &'a str,
+    title: &'a str,
+    status: EpisodeStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<&'a Path>,
+    /// Why the episode was skipped or failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_kind: Option<ErrorKind>,
+}
+
+impl<'a> EpisodeEvent<'a> {
+    fn new(podcast: &'a PodcastConfig, episode: &'a feed::Episode, status: EpisodeStatus) -> Self {
+        EpisodeEvent {
+            podcast: &podcast.name,
+            id: episode.id(),
+            title: &episode.title,
+            status,
+            file: None,
+            reason: None,
+            error_kind: None,
+        }
+    }
+}
+
+/// Print a run's failures, grouped by kind
+fn print_failures(failures: &[Failure]) {
+    if failures.is_empty() {
+        return;
+    }
+
+    println!("\n⚠️  Errors encountered while processing feeds:");
+    let mut by_kind: BTreeMap<ErrorKind, Vec<&Failure>> = BTreeMap::new();
+    for failure in failures {
+        by_kind.entry(failure.kind).or_default().push(failure);
+    }
+    for (kind, failures) in by_kind {
+        println!("  {} ({}):", kind, failures.len());
+        for failure in failures {
+            println!("    - {}", failure);
+        }
+    }
+}
+
+/// Outcome of checking one podcast
+struct PodcastReport {
+    downloaded: usize,
+    /// Episodes that failed to download
+    failures: Vec<Failure>,
 }
 
 async fn download_podcast(
     podcast: &PodcastConfig,
-    base_dir: Option<&std::path::Path>,
+    config: &Config,
+    http: &HttpClient,
     state: &mut State,
     max_episodes: Option<usize>,
-) -> Result<usize> {
+    fail_fast: bool,
+    out: &mut Output,
+) -> Result<PodcastReport> {
     let last_check = state.get_last_check(&podcast.name);
-    let output_dir = podcast.resolved_output_dir(base_dir)?;
+    let output_dir = podcast
+        .resolved_output_dir(config.base_dir.as_deref())
+        .map_err(PgError::config)?;
+    let http = &http.for_podcast(podcast).map_err(PgError::config)?;
 
     // Fetch and parse feed
-    let (episodes, image_url) = feed::fetch_feed(&podcast.url).await?;
-
-    // Download and cache the cover art if available
-    let cover_art_path = match image_url {
-        Some(url) => {
-            match image::download_and_convert_image(&url, &output_dir, &podcast.name).await {
-                Ok(path) => {
-                    info!("Downloaded cover art for '{}'", podcast.name);
-                    Some(path)
-                }
-                Err(e) => {
-                    tracing::warn!("Failed to download cover art for '{}': {}", podcast.name, e);
-                    None
+    let (episodes, feed_info) = fetch_podcast_feed(http, podcast, state).await?;
+
+    let mut selection = select_episodes(podcast, config, episodes, last_check, max_episodes)?;
+    for (episode, reason) in selection.skipped.drain(..) {
+        tracing::debug!("Skipping '{}': {}", episode.title, reason);
+        state.record_skipped(&podcast.name, episode.id(), &episode.title);
+        out.record(
+            "episode",
+            &EpisodeEvent {
+                reason: Some(reason),
+                ..EpisodeEvent::new(podcast, &episode, EpisodeStatus::Skipped)
+            },
+        )?;
+    }
+
+    let mut report = PodcastReport {
+        downloaded: 0,
+        failures: Vec::new(),
+    };
+
+    if selection.episodes.is_empty() {
+        // Still record the check, or a first sync that selects nothing would
+        // leave every later run backfilling again
+        info!("No new episodes for {}", podcast.name);
+        if let Some(checked) = selection.checked_until(None, Utc::now()) {
+            state.set_last_check(podcast.name.clone(), checked);
+        }
+        return Ok(report);
+    }
+
+    info!("Found {} new episodes for {}", selection.episodes.len(), podcast.name);
+
+    // Only needed once there are episodes to tag
+    let cover_art_path =
+        fetch_cover_art(http, config, podcast, &output_dir, feed_info.image_url.as_deref(), state).await;
+
+    let ctx = SaveContext {
+        http,
+        config,
+        podcast,
+        output_dir: &output_dir,
+        feed: &feed_info,
+        cover_art: cover_art_path.as_deref(),
+    };
+    let mut last_downloaded = None;
+    for episode in &selection.episodes {
+        match save_episode(&ctx, episode, state).await {
+            Ok(file_path) => {
+                report.downloaded += 1;
+                last_downloaded = Some(episode.pub_date);
+                out.record(
+                    "episode",
+                    &EpisodeEvent {
+                        file: Some(&file_path),
+                        ..EpisodeEvent::new(podcast, episode, EpisodeStatus::Downloaded)
+                    },
+                )?;
+            }
+            Err(e) => {
+                let failure = Failure::new(&podcast.name, Some(&episode.title), &e);
+                out.record(
+                    "episode",
+                    &EpisodeEvent {
+                        reason: Some(failure.message.clone()),
+                        error_kind: Some(failure.kind),
+                        ..EpisodeEvent::new(podcast, episode, EpisodeStatus::Failed)
+                    },
+                )?;
+                report.failures.push(failure);
+                if fail_fast {
+                    // Leave the last-check time alone so the rest are picked up next run
+                    return Ok(report);
                 }
             }
         }
+    }
+
+    // Update last check time
+    if let Some(checked) = selection.checked_until(last_downloaded, Utc::now()) {
+        state.set_last_check(podcast.name.clone(), checked);
+    }
+
+    Ok(report)
+}
+
+/// The episodes one sync of a podcast works through
+struct Selection {
+    /// Episodes to download, in the podcast's order
+    episodes: Vec<feed::Episode>,
+    /// Episodes left out by the podcast's filters or limit, and why
+    skipped: Vec<(feed::Episode, String)>,
+    /// Whether episodes past the limit were left for later syncs rather than
+    /// skipped, as with `order = "oldest"`
+    held_back: bool,
+}
+
+impl Selection {
+    /// What to record as the podcast's last check once the sync is done,
+    /// given the publication date of the last episode it downloaded. That's
+    /// normally `now`, but when episodes were held back it's the last one
+    /// downloaded, so the next sync carries on from there. `None` leaves the
+    /// last check as it was.
+    fn checked_until(&self, last_downloaded: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.held_back {
+            last_downloaded
+        } else {
+            Some(now)
+        }
+    }
+}
+
+/// Pick the episodes a sync downloads: those published since the last check,
+/// or the initial backfill on a first sync, narrowed by the podcast's filters
+/// and limit
+fn select_episodes(
+    podcast: &PodcastConfig,
+    config: &Config,
+    episodes: Vec<feed::Episode>,
+    last_check: Option<DateTime<Utc>>,
+    max_episodes: Option<usize>,
+) -> Result<Selection> {
+    // Filter by date, or by the initial backfill on a podcast's first sync
+    let new_episodes = match last_check {
+        Some(_) => feed::filter_by_date(episodes, last_check),
         None => {
-            info!("No cover art found for podcast '{}'", podcast.name);
-            None
+            let backfill = podcast.resolved_backfill(config.initial_backfill);
+            info!("First sync of '{}', backfilling {}", podcast.name, backfill);
+            feed::filter_backfill(episodes, backfill)
         }
     };
 
-    // Filter by date
-    let mut new_episodes = feed::filter_by_date(episodes, last_check);
+    // Apply the podcast's include/exclude filters
+    let (mut new_episodes, mut skipped) = EpisodeFilter::new(&podcast.filters)
+        .map_err(PgError::config)?
+        .apply(new_episodes);
 
-    // Apply max_episodes limit from CLI or config
+    // Sort so the max_episodes limit keeps the right end of the feed,
+    // whatever order the feed lists its items in
+    let order = podcast.order.unwrap_or_default();
+    feed::sort_episodes(&mut new_episodes, order);
+
+    // Apply max_episodes limit from CLI or config. Serialized shows work
+    // through the rest on later syncs; otherwise the older rest are skipped.
+    let mut held_back = false;
     let limit = max_episodes.or(podcast.max_episodes);
     if let Some(max) = limit {
-        new_episodes.truncate(max);
+        if new_episodes.len() > max {
+            let rest = new_episodes.split_off(max);
+            match order {
+                EpisodeOrder::Oldest => {
+                    info!("Leaving {} newer episodes of '{}' for later syncs", rest.len(), podcast.name);
+                    held_back = true;
+                }
+                EpisodeOrder::Newest => {
+                    let reason = format!("over the limit of {} episodes", max);
+                    skipped.extend(rest.into_iter().map(|e| (e, reason.clone())));
+                }
+            }
+        }
     }
 
-    if new_episodes.is_empty() {
-        info!("No new episodes for {}", podcast.name);
-        return Ok(0);
+    Ok(Selection {
+        episodes: new_episodes,
+        skipped,
+        held_back,
+    })
+}
+
+/// Fetch a podcast's feed, caching it and giving undated episodes a stable date
+async fn fetch_podcast_feed(
+    http: &HttpClient,
+    podcast: &PodcastConfig,
+    state: &mut State,
+) -> Result<(Vec<feed::Episode>, feed::FeedInfo)> {
+    let cache_file = podcast.feed_cache_file().ok();
+    let (mut episodes, feed_info) = feed::fetch_feed(http, &podcast.url, cache_file.as_deref()).await?;
+    prepare_episodes(podcast, &mut episodes, state);
+    Ok((episodes, feed_info))
+}
+
+/// Apply a podcast's enclosure preferences and give undated episodes a stable date
+fn prepare_episodes(podcast: &PodcastConfig, episodes: &mut [feed::Episode], state: &mut State) {
+    feed::choose_enclosures(episodes, &podcast.enclosure);
+
+    let undated = state.resolve_undated(&podcast.name, episodes);
+    if undated > 0 {
+        tracing::warn!(
+            "{} episode{} in '{}' had no parseable date, using the time first seen",
+            undated,
+            if undated == 1 { "" } else { "s" },
+            podcast.name
+        );
     }
+}
+
+/// Download the podcast's cover art, if the feed has any. The saved copy is
+/// reused while the feed's image URL stays the same, unless the server's
+/// ETag shows the image itself has changed.
+async fn fetch_cover_art(
+    http: &HttpClient,
+    config: &Config,
+    podcast: &PodcastConfig,
+    output_dir: &std::path::Path,
+    image_url: Option<&str>,
+    state: &mut State,
+) -> Option<PathBuf> {
+    let Some(url) = image_url else {
+        info!("No cover art found for podcast '{}'", podcast.name);
+        return None;
+    };
 
-    info!("Found {} new episodes for {}", new_episodes.len(), podcast.name);
+    let cover_filename = config.cover_filename.clone().unwrap_or_default();
+    let file_path = output_dir.join(cover_filename.render(&podcast.name));
+    let previous = state.cover_art(&podcast.name).map(|record| record.file.clone());
+
+    // A saved copy of this image that is still where it should be
+    let saved = state
+        .cover_art(&podcast.name)
+        .filter(|record| record.url == url)
+        .filter(|record| record.file == file_path && record.file.is_file())
+        .cloned();
+    let etag = match &saved {
+        // Without an ETag there's no cheap way to tell if it changed
+        Some(record) if record.etag.is_none() => return Some(record.file.clone()),
+        Some(record) => record.etag.as_deref(),
+        None => None,
+    };
 
-    let mut downloaded = 0;
-    for episode in new_episodes {
-        match download_episode(&output_dir, &episode).await {
-            Ok((file_path, prefixed_title)) => {
-                // Try to tag the file with cover art if available
-                if let Err(e) = tagger::tag_audio_file(&file_path, &podcast.name, &prefixed_title, cover_art_path.as_deref())
-                {
-                    tracing::warn!("Failed to tag file {}: {}", file_path.display(), e);
+    match image::download_and_convert_image(http, url, &file_path, cover_filename.format(), etag).await {
+        Ok(Some(image)) => {
+            info!("Downloaded cover art for '{}'", podcast.name);
+            // Artwork saved under another name before cover_filename changed,
+            // or by a version of pg that named it differently
+            let legacy = output_dir.join(CoverFilename::legacy_name(&podcast.name));
+            let stale = previous.into_iter().chain([legacy]);
+            for old in stale.filter(|old| *old != image.path && old.parent() == Some(output_dir)) {
+                if let Err(e) = std::fs::remove_file(&old) {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        tracing::warn!("Failed to remove old cover art {}: {}", old.display(), e);
+                    }
                 }
-                downloaded += 1;
-            }
-            Err(e) => {
-                tracing::warn!(
-                    "Failed to download episode '{}': {}",
-                    episode.title,
-                    e
-                );
             }
+            state.record_cover_art(&podcast.name, url, image.etag, image.path.clone());
+            Some(image.path)
+        }
+        Ok(None) => {
+            tracing::debug!("Cover art for '{}' is unchanged", podcast.name);
+            saved.map(|record| record.file)
+        }
+        Err(e) => {
+            tracing::warn!("Failed to download cover art for '{}': {}", podcast.name, e);
+            saved.map(|record| record.file)
         }
     }
+}
 
-    // Update last check time
-    state.set_last_check(podcast.name.clone(), Utc::now());
+/// Everything needed to save and tag one podcast's episodes
+struct SaveContext<'a> {
+    http: &'a HttpClient,
+    config: &'a Config,
+    podcast: &'a PodcastConfig,
+    output_dir: &'a Path,
+    feed: &'a feed::FeedInfo,
+    cover_art: Option<&'a Path>,
+}
+
+/// The ffmpeg processing to run on an episode, if any. Processing keeps only
+/// the audio, so video episodes are left as downloaded.
+fn processing_for(
+    podcast: &PodcastConfig,
+    config: &Config,
+    episode: &feed::Episode,
+) -> Option<ProcessingSettings> {
+    let settings = podcast.resolved_processing(&config.processing)?;
+    if episode.is_video() {
+        tracing::debug!("Not processing '{}', a video episode", episode.title);
+        return None;
+    }
+    Some(settings)
+}
+
+/// Download and tag a single episode, recording the outcome in state
+async fn save_episode(ctx: &SaveContext<'_>, episode: &feed::Episode, state: &mut State) -> Result<PathBuf> {
+    let podcast = ctx.podcast;
+    match download_episode(ctx.http, ctx.output_dir, episode).await {
+        Ok(downloaded) => {
+            let processed = match processing_for(podcast, ctx.config, episode) {
+                Some(settings) => processing::process_episode(&downloaded, &settings)
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::warn!("Failed to process '{}', keeping it as downloaded: {}", episode.title, e);
+                        processing::Processed {
+                            path: downloaded.clone(),
+                            original: None,
+                        }
+                    }),
+                None => processing::Processed {
+                    path: downloaded,
+                    original: None,
+                },
+            };
+            let file_path = processed.path;
+
+            let chapters = match &episode.chapters_url {
+                Some(url) => chapters::fetch_chapters(ctx.http, url, episode.duration, &file_path)
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::warn!("Failed to fetch chapters for '{}': {}", episode.title, e);
+                        Vec::new()
+                    }),
+                None => Vec::new(),
+            };
+
+            let transcript = match podcast.resolved_transcripts(&ctx.config.transcripts) {
+                Some(settings) => {
+                    transcripts::fetch_transcript(ctx.http, &episode.transcripts, settings, &file_path)
+                        .await
+                        .unwrap_or_else(|e| {
+                            tracing::warn!("Failed to fetch transcript for '{}': {}", episode.title, e);
+                            None
+                        })
+                }
+                None => None,
+            };
+
+            // Episodes with their own artwork get it instead of the podcast's
+            let episode_art = match episode.image_url.as_deref() {
+                Some(url) if Some(url) != ctx.feed.image_url.as_deref() => {
+                    match image::cached_episode_image(ctx.http, url).await {
+                        Ok(path) => Some(path),
+                        Err(e) => {
+                            tracing::warn!("Failed to download artwork for '{}': {}", episode.title, e);
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            };
 
-    Ok(downloaded)
+            let replaygain = if podcast.resolved_replaygain(ctx.config.replaygain) {
+                replaygain::measure(&file_path, &podcast.name, episode.id(), state).await
+            } else {
+                None
+            };
+
+            // Try to tag the file with cover art if available
+            let mapping = podcast.resolved_tags(&ctx.config.tags);
+            let tagged = tagger::Tags::for_episode(&mapping, podcast, ctx.feed, episode).and_then(|tags| {
+                let cover_art = episode_art
+                    .or_else(|| ctx.cover_art.map(Path::to_path_buf))
+                    .map(|path| {
+                        image::embed_copy(&path, &ctx.config.cover).unwrap_or_else(|e| {
+                            tracing::warn!("Failed to prepare cover art {}: {}", path.display(), e);
+                            path
+                        })
+                    });
+                let tags = tagger::Tags {
+                    cover_art,
+                    chapters,
+                    lyrics: transcript.as_ref().and_then(|t| t.lyrics.clone()),
+                    replaygain: replaygain.as_ref().map(|(values, _)| *values),
+                    ..tags
+                };
+                tagger::tag_audio_file(ctx.config.tagger.unwrap_or_default(), &file_path, &tags)
+            });
+            if let Err(e) = tagged {
+                tracing::warn!("Failed to tag file {}: {}", file_path.display(), e);
+            }
+            state.record_download(&podcast.name, episode.id(), &episode.title, file_path.clone());
+            if let Some(transcript) = transcript {
+                state.record_transcript(&podcast.name, episode.id(), transcript.path);
+            }
+            if let Some(original) = processed.original {
+                state.record_original(&podcast.name, episode.id(), original);
+            }
+            if let Some((values, sha256)) = replaygain {
+                state.record_replaygain(&podcast.name, episode.id(), values, sha256);
+            }
+            Ok(file_path)
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Failed to download episode '{}': {}",
+                episode.title,
+                e
+            );
+            state.record_failure(&podcast.name, episode.id(), &episode.title, e.to_string());
+            Err(e)
+        }
+    }
 }
 
-async fn download_episode(output_dir: &std::path::Path, episode: &feed::Episode) -> Result<(PathBuf, String)> {
-    // Extract file extension from URL
-    let extension = extract_extension(&episode.url).unwrap_or("mp3");
+async fn download_episode(
+    http: &HttpClient,
+    output_dir: &std::path::Path,
+    episode: &feed::Episode,
+) -> Result<PathBuf> {
+    // Extract file extension from URL, falling back to the enclosure's MIME type
+    let extension = extract_extension(&episode.url)
+        .or_else(|| episode.mime_type.as_deref().and_then(download::extension_for_mime))
+        .unwrap_or("mp3");
 
     // Prefix title with publication date for chronological sorting
     let date_prefix = episode.pub_date.format("%Y-%m-%d");
@@ -143,14 +585,14 @@ async fn download_episode(output_dir: &std::path::Path, episode: &feed::Episode)
         file_path.display()
     );
 
-    download::download_file(&episode.url, &file_path).await?;
+    download::download_file(http, &episode.url, &file_path, episode.size).await?;
 
-    Ok((file_path, prefixed_title))
+    Ok(file_path)
 }
 
 fn extract_extension(url: &str) -> Option<&str> {
     url.split('.')
-        .last()
+        .next_back()
         .and_then(|part| {
             // Take only the part before any query string
             part.split('?').next()
@@ -166,10 +608,7 @@ fn extract_extension(url: &str) -> Option<&str> {
 }
 
 pub fn add_podcast(url: String, name: Option<String>, output_dir: Option<PathBuf>) -> Result<()> {
-    let mut config = Config::load().unwrap_or(Config {
-        base_dir: None,
-        podcasts: vec![],
-    });
+    let mut config = Config::load().unwrap_or_default();
 
     let podcast_name = name.unwrap_or_else(|| {
         // Try to extract name from URL
@@ -183,8 +622,7 @@ pub fn add_podcast(url: String, name: Option<String>, output_dir: Option<PathBuf
         name: podcast_name.clone(),
         url,
         output_dir,
-        max_episodes: None,
-        paused: false,
+        ..Default::default()
     });
 
     config.save()?;
@@ -193,10 +631,40 @@ pub fn add_podcast(url: String, name: Option<String>, output_dir: Option<PathBuf
     Ok(())
 }
 
-pub fn list_podcasts() -> Result<()> {
+/// One podcast in `pg list` JSON output
+#[derive(Serialize)]
+struct PodcastRow<'a> {
+    name: &'a str,
+    url: &'a str,
+    output_dir: Option<PathBuf>,
+    paused: bool,
+    /// Where credentials come from, never their values
+    auth: Option<String>,
+    last_checked: Option<chrono::DateTime<Utc>>,
+}
+
+pub fn list_podcasts(format: OutputFormat) -> Result<()> {
     let config = Config::load()?;
     let state = State::load()?;
 
+    let mut out = Output::new(format, "list");
+    if !out.is_table() {
+        for podcast in &config.podcasts {
+            out.record(
+                "podcast",
+                &PodcastRow {
+                    name: &podcast.name,
+                    url: &podcast.url,
+                    output_dir: podcast.resolved_output_dir(config.base_dir.as_deref()).ok(),
+                    paused: podcast.paused,
+                    auth: podcast.auth.as_ref().map(|auth| auth.to_string()),
+                    last_checked: state.get_last_check(&podcast.name),
+                },
+            )?;
+        }
+        return out.finish();
+    }
+
     if config.podcasts.is_empty() {
         println!("No podcasts configured.");
         return Ok(());
@@ -212,6 +680,9 @@ pub fn list_podcasts() -> Result<()> {
             Ok(dir) => println!("Output: {}", dir.display()),
             Err(e) => println!("Output: <unresolved: {}>", e),
         }
+        if let Some(auth) = &podcast.auth {
+            println!("Auth: {}", auth);
+        }
 
         if let Some(last_check) = state.get_last_check(&podcast.name) {
             println!("Last checked: {}", last_check.format("%Y-%m-%d %H:%M:%S UTC"));
@@ -225,10 +696,36 @@ pub fn list_podcasts() -> Result<()> {
     Ok(())
 }
 
-pub fn show_status() -> Result<()> {
+/// One podcast in `pg status` JSON output
+#[derive(Serialize)]
+struct StatusRow<'a> {
+    name: &'a str,
+    paused: bool,
+    last_checked: Option<chrono::DateTime<Utc>>,
+    /// Episodes dated by when they were first seen
+    undated_episodes: usize,
+}
+
+pub fn show_status(format: OutputFormat) -> Result<()> {
     let config = Config::load()?;
     let state = State::load()?;
 
+    let mut out = Output::new(format, "status");
+    if !out.is_table() {
+        for podcast in &config.podcasts {
+            out.record(
+                "status",
+                &StatusRow {
+                    name: &podcast.name,
+                    paused: podcast.paused,
+                    last_checked: state.get_last_check(&podcast.name),
+                    undated_episodes: state.undated_count(&podcast.name),
+                },
+            )?;
+        }
+        return out.finish();
+    }
+
     if config.podcasts.is_empty() {
         println!("No podcasts configured.");
         return Ok(());
@@ -238,13 +735,24 @@ pub fn show_status() -> Result<()> {
 
     for podcast in &config.podcasts {
         let paused_indicator = if podcast.paused { " (paused)" } else { "" };
+        let undated = state.undated_count(&podcast.name);
+        let undated_warning = if undated > 0 {
+            format!(
+                " ({} episode{} with unparseable dates)",
+                undated,
+                if undated == 1 { "" } else { "s" }
+            )
+        } else {
+            String::new()
+        };
         match state.get_last_check(&podcast.name) {
             Some(last_check) => {
                 println!(
-                    "{}{}: last checked {}",
+                    "{}{}: last checked {}{}",
                     podcast.name,
                     paused_indicator,
-                    last_check.format("%Y-%m-%d %H:%M:%S UTC")
+                    last_check.format("%Y-%m-%d %H:%M:%S UTC"),
+                    undated_warning
                 );
             }
             None => {
@@ -256,129 +764,597 @@ pub fn show_status() -> Result<()> {
     Ok(())
 }
 
-pub async fn update_feed(podcast_name: String) -> Result<()> {
+/// One row of `pg episodes` output
+#[derive(Serialize)]
+struct EpisodeRow {
+    index: usize,
+    id: String,
+    title: String,
+    date: String,
+    duration: Option<u64>,
+    size: Option<u64>,
+    status: Option<&'static str>,
+    url: String,
+    /// Saved transcript, for downloaded episodes that have one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transcript: Option<PathBuf>,
+    /// Why the episode would be kept or skipped, with `--explain`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explain: Option<String>,
+}
+
+pub async fn list_episodes(
+    podcast_name: String,
+    search: Option<String>,
+    since: Option<NaiveDate>,
+    limit: Option<usize>,
+    cached: bool,
+    explain: bool,
+    format: OutputFormat,
+) -> Result<()> {
     let config = Config::load()?;
+    // Listing works alongside a running download, but only saves state if
+    // nothing else is writing it
+    let lock = State::lock().ok();
+    let mut state = State::load()?;
+
+    let podcast = config.podcast(&podcast_name)?;
+
+    let http = HttpClient::new(&config.http)?.for_podcast(podcast)?;
+    let cache_file = podcast.feed_cache_file()?;
+    let (mut episodes, _) = if cached {
+        feed::load_cached_feed(&cache_file)?
+    } else {
+        match feed::fetch_feed(&http, &podcast.url, Some(&cache_file)).await {
+            Ok(parsed) => parsed,
+            Err(e) if cache_file.exists() => {
+                tracing::warn!("Failed to fetch feed for '{}', using cached copy: {}", podcast.name, e);
+                feed::load_cached_feed(&cache_file)?
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    prepare_episodes(podcast, &mut episodes, &mut state);
+    if lock.is_some() {
+        state.save()?;
+    }
+
+    let filter = EpisodeFilter::new(&podcast.filters)?;
+    let last_check = state.get_last_check(&podcast.name);
 
-    let podcast = config
-        .podcasts
+    let search = search.map(|s| s.to_lowercase());
+    let rows: Vec<EpisodeRow> = episodes
         .iter()
-        .find(|p| p.name == podcast_name)
-        .ok_or_else(|| {
-            anyhow::anyhow!("Podcast '{}' not found in config", podcast_name)
-        })?;
+        .enumerate()
+        .filter(|(_, ep)| {
+            search
+                .as_ref()
+                .is_none_or(|s| ep.title.to_lowercase().contains(s))
+        })
+        .filter(|(_, ep)| since.is_none_or(|d| ep.pub_date.date_naive() >= d))
+        .take(limit.unwrap_or(usize::MAX))
+        .map(|(i, ep)| EpisodeRow {
+            index: i + 1,
+            id: ep.id().to_string(),
+            title: ep.title.clone(),
+            date: ep.pub_date.format("%Y-%m-%d").to_string(),
+            duration: ep.duration,
+            size: ep.size,
+            status: state
+                .episode(&podcast.name, ep.id())
+                .map(|r| r.status.as_str()),
+            url: ep.url.clone(),
+            transcript: state
+                .episode(&podcast.name, ep.id())
+                .and_then(|r| r.transcript.clone()),
+            explain: explain.then(|| explain_episode(ep, &filter, last_check)),
+        })
+        .collect();
+
+    let mut out = Output::new(format, "episodes");
+    if !out.is_table() {
+        for row in &rows {
+            out.record("episode", row)?;
+        }
+        return out.finish();
+    }
+
+    if rows.is_empty() {
+        println!("No matching episodes.");
+        return Ok(());
+    }
+
+    for row in &rows {
+        println!(
+            "{:>4}  {}  {:>8}  {:>8}  {:<10}  {}",
+            row.index,
+            row.date,
+            row.duration.map(format_duration).unwrap_or_else(|| "-".to_string()),
+            row.size.map(format_size).unwrap_or_else(|| "-".to_string()),
+            row.status.unwrap_or("-"),
+            row.title
+        );
+        if let Some(explanation) = &row.explain {
+            println!("{:>6}{}", "", explanation);
+        }
+    }
+
+    Ok(())
+}
+
+/// Describe whether a download run would keep or skip an episode, and why
+fn explain_episode(
+    episode: &feed::Episode,
+    filter: &EpisodeFilter,
+    last_check: Option<chrono::DateTime<Utc>>,
+) -> String {
+    if let Some(reason) = filter.skip_reason(episode) {
+        return format!("skipped: {}", reason);
+    }
+    match last_check {
+        Some(cutoff) if episode.pub_date <= cutoff => {
+            "kept by filters, but published before the last check".to_string()
+        }
+        _ => "kept".to_string(),
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    let (h, m, s) = (secs / 3600, (secs % 3600) / 60, secs % 60);
+    if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{}:{:02}", m, s)
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const MB: f64 = 1024.0 * 1024.0;
+    format!("{:.1} MB", bytes as f64 / MB)
+}
+
+pub async fn update_feed(podcast_name: String, fail_fast: bool, format: OutputFormat) -> Result<ExitStatus> {
+    let config = Config::load()?;
+
+    let podcast = config.podcast(&podcast_name)?;
 
     if podcast.paused {
         tracing::warn!("Podcast '{}' is paused, but updating anyway since it was explicitly requested", podcast.name);
     }
 
+    let _lock = State::lock()?;
     let mut state = State::load()?;
 
-    match download_podcast(podcast, config.base_dir.as_deref(), &mut state, None).await {
-        Ok(count) => {
-            info!("Downloaded {} new episodes from {}", count, podcast.name);
+    let http = HttpClient::new(&config.http).map_err(PgError::config)?;
+
+    let mut out = Output::new(format, "update-feed");
+    match download_podcast(podcast, &config, &http, &mut state, None, fail_fast, &mut out).await {
+        Ok(report) => {
+            info!("Downloaded {} new episodes from {}", report.downloaded, podcast.name);
             state.save()?;
+            if out.is_table() {
+                for failure in &report.failures {
+                    println!("Failed: {}", failure);
+                }
+            }
+            let succeeded = usize::from(report.downloaded > 0 || report.failures.is_empty());
+            let status = ExitStatus::for_run(&report.failures, succeeded);
+            out.summary(&DownloadSummary {
+                podcasts: 1,
+                downloaded: report.downloaded,
+                failed: report.failures.len(),
+                exit_code: status.code(),
+                failures: &report.failures,
+            })?;
+            out.finish()?;
+            Ok(status)
+        }
+        Err(e) => Err(e.context("Failed to update feed")),
+    }
+}
+
+pub async fn get_episode(podcast_name: String, selector: String, format: OutputFormat) -> Result<()> {
+    let config = Config::load()?;
+    let _lock = State::lock()?;
+
+    let podcast = config.podcast(&podcast_name)?;
+
+    let mut state = State::load()?;
+    let output_dir = podcast.resolved_output_dir(config.base_dir.as_deref())?;
+    let http = HttpClient::new(&config.http)?.for_podcast(podcast)?;
+
+    let (episodes, feed_info) = fetch_podcast_feed(&http, podcast, &mut state).await?;
+    let episode = feed::find_episode(&episodes, &selector)?;
+
+    let cover_art_path =
+        fetch_cover_art(&http, &config, podcast, &output_dir, feed_info.image_url.as_deref(), &mut state).await;
+
+    // The last-check time is left alone so regular downloads are unaffected
+    let ctx = SaveContext {
+        http: &http,
+        config: &config,
+        podcast,
+        output_dir: &output_dir,
+        feed: &feed_info,
+        cover_art: cover_art_path.as_deref(),
+    };
+    let result = save_episode(&ctx, episode, &mut state).await;
+    state.save()?;
+
+    let mut out = Output::new(format, "get");
+    match &result {
+        Ok(file_path) => {
+            if out.is_table() {
+                println!("Downloaded '{}' to {}", episode.title, file_path.display());
+            }
+            out.record(
+                "episode",
+                &EpisodeEvent {
+                    file: Some(file_path),
+                    ..EpisodeEvent::new(podcast, episode, EpisodeStatus::Downloaded)
+                },
+            )?;
         }
         Err(e) => {
-            anyhow::bail!("Failed to update feed: {}", e);
+            let failure = Failure::new(&podcast.name, Some(&episode.title), e);
+            out.record(
+                "episode",
+                &EpisodeEvent {
+                    reason: Some(failure.message),
+                    error_kind: Some(failure.kind),
+                    ..EpisodeEvent::new(podcast, episode, EpisodeStatus::Failed)
+                },
+            )?;
         }
     }
+    // The JSON document is printed even when the download failed
+    out.finish()?;
 
-    Ok(())
+    result.map(|_| ())
 }
 
-pub fn clean_podcasts() -> Result<()> {
+pub async fn backfill_podcast(podcast_name: String, since: NaiveDate, format: OutputFormat) -> Result<ExitStatus> {
     let config = Config::load()?;
+    let _lock = State::lock()?;
 
-    if config.podcasts.is_empty() {
+    let podcast = config.podcast(&podcast_name)?;
+
+    let mut state = State::load()?;
+    let output_dir = podcast.resolved_output_dir(config.base_dir.as_deref())?;
+    let http = HttpClient::new(&config.http)?.for_podcast(podcast)?;
+
+    let (episodes, feed_info) = fetch_podcast_feed(&http, podcast, &mut state).await?;
+
+    // Pull the archive since the given date, skipping anything filtered out
+    // or already downloaded
+    let (archive, _) = EpisodeFilter::new(&podcast.filters)?
+        .apply(feed::filter_backfill(episodes, Backfill::Since(since)));
+    let archive: Vec<feed::Episode> = archive
+        .into_iter()
+        .filter(|ep| {
+            state
+                .episode(&podcast.name, ep.id())
+                .is_none_or(|r| r.status != EpisodeStatus::Downloaded)
+        })
+        .collect();
+
+    let mut out = Output::new(format, "backfill");
+    if archive.is_empty() {
+        if out.is_table() {
+            println!("No episodes to backfill for '{}' since {}", podcast.name, since);
+        }
+        out.summary(&DownloadSummary {
+            podcasts: 1,
+            downloaded: 0,
+            failed: 0,
+            exit_code: ExitStatus::Success.code(),
+            failures: &[],
+        })?;
+        out.finish()?;
+        return Ok(ExitStatus::Success);
+    }
+
+    info!("Backfilling {} episodes for {}", archive.len(), podcast.name);
+
+    let cover_art_path =
+        fetch_cover_art(&http, &config, podcast, &output_dir, feed_info.image_url.as_deref(), &mut state).await;
+
+    // The last-check time is left alone so regular downloads are unaffected
+    let ctx = SaveContext {
+        http: &http,
+        config: &config,
+        podcast,
+        output_dir: &output_dir,
+        feed: &feed_info,
+        cover_art: cover_art_path.as_deref(),
+    };
+    let mut downloaded = 0;
+    let mut failures = Vec::new();
+    for episode in &archive {
+        match save_episode(&ctx, episode, &mut state).await {
+            Ok(file_path) => {
+                downloaded += 1;
+                out.record(
+                    "episode",
+                    &EpisodeEvent {
+                        file: Some(&file_path),
+                        ..EpisodeEvent::new(podcast, episode, EpisodeStatus::Downloaded)
+                    },
+                )?;
+            }
+            Err(e) => {
+                let failure = Failure::new(&podcast.name, Some(&episode.title), &e);
+                out.record(
+                    "episode",
+                    &EpisodeEvent {
+                        reason: Some(failure.message.clone()),
+                        error_kind: Some(failure.kind),
+                        ..EpisodeEvent::new(podcast, episode, EpisodeStatus::Failed)
+                    },
+                )?;
+                failures.push(failure);
+            }
+        }
+        state.save()?;
+    }
+
+    let status = ExitStatus::for_run(&failures, usize::from(downloaded > 0));
+    if out.is_table() {
+        println!(
+            "Backfilled {} of {} episode{} for '{}'",
+            downloaded,
+            archive.len(),
+            if archive.len() == 1 { "" } else { "s" },
+            podcast.name
+        );
+        print_failures(&failures);
+    }
+    out.summary(&DownloadSummary {
+        podcasts: 1,
+        downloaded,
+        failed: failures.len(),
+        exit_code: status.code(),
+        failures: &failures,
+    })?;
+    out.finish()?;
+
+    Ok(status)
+}
+
+/// One podcast in `pg clean` JSON output
+#[derive(Serialize)]
+struct CleanRow<'a> {
+    podcast: &'a str,
+    deleted: usize,
+    /// Media files deleted from a directory pg has no download records for
+    #[serde(skip_serializing_if = "is_zero")]
+    unrecorded: usize,
+    /// Why the podcast's directory wasn't cleaned
+    #[serde(skip_serializing_if = "Option::is_none")]
+    skipped: Option<String>,
+}
+
+impl<'a> CleanRow<'a> {
+    fn skipped(podcast: &'a str, deleted: usize, reason: String) -> Self {
+        CleanRow {
+            podcast,
+            deleted,
+            unrecorded: 0,
+            skipped: Some(reason),
+        }
+    }
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+/// Closing summary of `pg clean` in JSON output
+#[derive(Serialize)]
+struct CleanSummary<'a> {
+    deleted: usize,
+    #[serde(skip_serializing_if = "is_zero")]
+    unrecorded: usize,
+    errors: &'a [String],
+}
+
+pub fn clean_podcasts(format: OutputFormat) -> Result<()> {
+    let config = Config::load()?;
+    let _lock = State::lock()?;
+    let mut state = State::load()?;
+    let mut out = Output::new(format, "clean");
+
+    if config.podcasts.is_empty() && out.is_table() {
         println!("No podcasts configured.");
         return Ok(());
     }
 
     let mut total_deleted = 0;
+    let mut total_unrecorded = 0;
     let mut errors = Vec::new();
 
-    println!("Cleaning MP3 files from configured podcast directories...\n");
+    if out.is_table() {
+        println!("Cleaning episode files from configured podcast directories...\n");
+    }
 
     for podcast in &config.podcasts {
+        let mut podcast_deleted = 0;
+        let mut podcast_unrecorded = 0;
+        let has_records = state.has_downloads(&podcast.name);
+
+        // Files pg recorded as downloaded, wherever they were saved
+        for path in state.downloaded_files(&podcast.name) {
+            // The saved chapters, transcript and original go with their episode
+            let companions = [
+                Some(chapters::sidecar_path(&path)),
+                state.transcript_for(&podcast.name, &path),
+                state.original_for(&podcast.name, &path),
+            ];
+            let mut leftover = false;
+            for companion in companions.into_iter().flatten() {
+                if let Err(e) = std::fs::remove_file(&companion) {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        let error_msg = format!("Failed to delete {}: {}", companion.display(), e);
+                        tracing::warn!("{}", error_msg);
+                        errors.push(error_msg);
+                        leftover = true;
+                    }
+                }
+            }
+            if path.is_file() {
+                match std::fs::remove_file(&path) {
+                    Ok(_) => podcast_deleted += 1,
+                    Err(e) => {
+                        let error_msg = format!("Failed to delete {}: {}", path.display(), e);
+                        tracing::warn!("{}", error_msg);
+                        errors.push(error_msg);
+                        continue;
+                    }
+                }
+            }
+            // Keep the record of anything left behind, so the next clean retries it
+            if !leftover {
+                state.clear_file(&podcast.name, &path);
+            }
+        }
+
         let output_dir = match podcast.resolved_output_dir(config.base_dir.as_deref()) {
             Ok(dir) => dir,
             Err(e) => {
-                println!("Skipping '{}': {}", podcast.name, e);
+                let reason = e.to_string();
+                if out.is_table() {
+                    println!("Skipping '{}': {}", podcast.name, reason);
+                }
+                out.record("clean", &CleanRow::skipped(&podcast.name, podcast_deleted, reason))?;
+                total_deleted += podcast_deleted;
                 continue;
             }
         };
 
-        if !output_dir.exists() {
-            println!("Skipping '{}': directory does not exist", podcast.name);
-            continue;
-        }
-
-        match std::fs::read_dir(&output_dir) {
-            Ok(entries) => {
-                let mut podcast_deleted = 0;
-
-                for entry in entries {
-                    match entry {
-                        Ok(entry) => {
-                            let path = entry.path();
-                            if path.is_file() {
-                                if let Some(extension) = path.extension() {
-                                    if extension.to_string_lossy().to_lowercase() == "mp3" {
-                                        match std::fs::remove_file(&path) {
-                                            Ok(_) => {
-                                                podcast_deleted += 1;
-                                                total_deleted += 1;
-                                            }
-                                            Err(e) => {
-                                                let error_msg = format!(
-                                                    "Failed to delete {}: {}",
-                                                    path.display(),
-                                                    e
-                                                );
-                                                tracing::warn!("{}", error_msg);
-                                                errors.push(error_msg);
-                                            }
+        // Fall back to recognized media files only for podcasts downloaded
+        // before pg recorded its downloads. Once there are records, anything
+        // else in the directory belongs to the user or another tool.
+        if has_records {
+            if !output_dir.exists() && podcast_deleted == 0 {
+                if out.is_table() {
+                    println!("Skipping '{}': directory does not exist", podcast.name);
+                }
+                out.record(
+                    "clean",
+                    &CleanRow::skipped(&podcast.name, 0, "directory does not exist".to_string()),
+                )?;
+                continue;
+            }
+        } else if output_dir.exists() {
+            match std::fs::read_dir(&output_dir) {
+                Ok(entries) => {
+                    for entry in entries {
+                        match entry {
+                            Ok(entry) => {
+                                let path = entry.path();
+                                if path.is_file() && download::is_media_file(&path) {
+                                    match std::fs::remove_file(&path) {
+                                        Ok(_) => podcast_unrecorded += 1,
+                                        Err(e) => {
+                                            let error_msg = format!(
+                                                "Failed to delete {}: {}",
+                                                path.display(),
+                                                e
+                                            );
+                                            tracing::warn!("{}", error_msg);
+                                            errors.push(error_msg);
                                         }
                                     }
                                 }
                             }
-                        }
-                        Err(e) => {
-                            let error_msg = format!(
-                                "Failed to read entry in {}: {}",
-                                output_dir.display(),
-                                e
-                            );
-                            tracing::warn!("{}", error_msg);
-                            errors.push(error_msg);
+                            Err(e) => {
+                                let error_msg = format!(
+                                    "Failed to read entry in {}: {}",
+                                    output_dir.display(),
+                                    e
+                                );
+                                tracing::warn!("{}", error_msg);
+                                errors.push(error_msg);
+                            }
                         }
                     }
                 }
-
-                if podcast_deleted > 0 {
-                    println!(
-                        "Deleted {} MP3 file{} from '{}'",
-                        podcast_deleted,
-                        if podcast_deleted == 1 { "" } else { "s" },
-                        podcast.name
+                Err(e) => {
+                    let error_msg = format!(
+                        "Failed to read directory for '{}': {}",
+                        podcast.name, e
                     );
+                    tracing::error!("{}", error_msg);
+                    errors.push(error_msg);
                 }
             }
-            Err(e) => {
-                let error_msg = format!(
-                    "Failed to read directory for '{}': {}",
-                    podcast.name, e
-                );
-                tracing::error!("{}", error_msg);
-                errors.push(error_msg);
+        } else if podcast_deleted == 0 {
+            if out.is_table() {
+                println!("Skipping '{}': directory does not exist", podcast.name);
             }
+            out.record(
+                "clean",
+                &CleanRow::skipped(&podcast.name, 0, "directory does not exist".to_string()),
+            )?;
+            continue;
+        }
+
+        out.record(
+            "clean",
+            &CleanRow {
+                podcast: &podcast.name,
+                deleted: podcast_deleted,
+                unrecorded: podcast_unrecorded,
+                skipped: None,
+            },
+        )?;
+        if podcast_deleted > 0 && out.is_table() {
+            println!(
+                "Deleted {} episode file{} from '{}'",
+                podcast_deleted,
+                if podcast_deleted == 1 { "" } else { "s" },
+                podcast.name
+            );
+        }
+        if podcast_unrecorded > 0 && out.is_table() {
+            println!(
+                "Deleted {} unrecorded media file{} from '{}'",
+                podcast_unrecorded,
+                if podcast_unrecorded == 1 { "" } else { "s" },
+                podcast.name
+            );
         }
+        total_deleted += podcast_deleted;
+        total_unrecorded += podcast_unrecorded;
     }
 
+    if let Err(e) = image::clear_cache() {
+        let error_msg = e.to_string();
+        tracing::warn!("{}", error_msg);
+        errors.push(error_msg);
+    }
+
+    state.save()?;
+
+    if !out.is_table() {
+        out.summary(&CleanSummary {
+            deleted: total_deleted,
+            unrecorded: total_unrecorded,
+            errors: &errors,
+        })?;
+        return out.finish();
+    }
+
+    let unrecorded = match total_unrecorded {
+        0 => String::new(),
+        1 => ", and 1 unrecorded media file".to_string(),
+        n => format!(", and {} unrecorded media files", n),
+    };
     println!(
-        "\nTotal: deleted {} MP3 file{}",
+        "\nTotal: deleted {} episode file{}{}",
         total_deleted,
-        if total_deleted == 1 { "" } else { "s" }
+        if total_deleted == 1 { "" } else { "s" },
+        unrecorded
     );
 
     // Report errors if any
@@ -393,10 +1369,7 @@ pub fn clean_podcasts() -> Result<()> {
 }
 
 pub fn print_podcast_names() -> Result<()> {
-    let config = Config::load().unwrap_or(Config {
-        base_dir: None,
-        podcasts: vec![],
-    });
+    let config = Config::load().unwrap_or_default();
     for podcast in &config.podcasts {
         println!("{}", podcast.name);
     }
@@ -417,24 +1390,69 @@ const BASH_COMPLETION: &str = r#"_pg() {
     local cur prev words cword
     _init_completion || return
 
-    local subcommands="download add list status update-feed init-config clean pause unpause completions"
+    local subcommands="download add list status episodes get backfill update-feed init-config clean pause unpause completions"
 
     if [[ $cword -eq 1 ]]; then
         COMPREPLY=($(compgen -W "$subcommands" -- "$cur"))
         return
     fi
 
+    if [[ "$prev" == "--output" ]]; then
+        COMPREPLY=($(compgen -W "table json ndjson" -- "$cur"))
+        return
+    fi
+
     case "${words[1]}" in
-        update-feed|pause|unpause)
+        update-feed)
+            if [[ $cword -eq 2 ]]; then
+                local names
+                names=$(pg names 2>/dev/null)
+                COMPREPLY=($(compgen -W "$names" -- "$cur"))
+            else
+                COMPREPLY=($(compgen -W "--fail-fast --output --debug -d" -- "$cur"))
+            fi
+            ;;
+        pause|unpause)
             local names
             names=$(pg names 2>/dev/null)
             COMPREPLY=($(compgen -W "$names" -- "$cur"))
             ;;
+        get)
+            if [[ $cword -eq 2 ]]; then
+                local names
+                names=$(pg names 2>/dev/null)
+                COMPREPLY=($(compgen -W "$names" -- "$cur"))
+            fi
+            ;;
+        backfill)
+            case "$prev" in
+                --since) return ;;
+            esac
+            if [[ $cword -eq 2 ]]; then
+                local names
+                names=$(pg names 2>/dev/null)
+                COMPREPLY=($(compgen -W "$names" -- "$cur"))
+            else
+                COMPREPLY=($(compgen -W "--since --debug -d" -- "$cur"))
+            fi
+            ;;
+        episodes)
+            case "$prev" in
+                -s|--search|--since|-l|--limit) return ;;
+            esac
+            if [[ $cword -eq 2 ]]; then
+                local names
+                names=$(pg names 2>/dev/null)
+                COMPREPLY=($(compgen -W "$names" -- "$cur"))
+            else
+                COMPREPLY=($(compgen -W "--search -s --since --limit -l --cached --explain --json --output --debug -d" -- "$cur"))
+            fi
+            ;;
         download)
             case "$prev" in
                 -m|--max-episodes) return ;;
             esac
-            COMPREPLY=($(compgen -W "--max-episodes -m --debug -d" -- "$cur"))
+            COMPREPLY=($(compgen -W "--max-episodes -m --fail-fast --output --debug -d" -- "$cur"))
             ;;
         add)
             case "$prev" in
@@ -442,6 +1460,9 @@ const BASH_COMPLETION: &str = r#"_pg() {
             esac
             COMPREPLY=($(compgen -W "--name -n --output-dir -o --debug -d" -- "$cur"))
             ;;
+        list|status|clean)
+            COMPREPLY=($(compgen -W "--output --debug -d" -- "$cur"))
+            ;;
         completions)
             COMPREPLY=($(compgen -W "bash zsh fish" -- "$cur"))
             ;;
@@ -458,6 +1479,7 @@ _pg() {
 
     _arguments \
         '(-d --debug)'{-d,--debug}'[Enable debug logging]' \
+        '--output[Output format]:format:(table json ndjson)' \
         ':command:->command' \
         '*::args:->args'
 
@@ -468,9 +1490,12 @@ _pg() {
                 'add:Add a new podcast to the config'
                 'list:List all configured podcasts'
                 'status:Show last-check timestamps for all podcasts'
+                'episodes:List the episodes in a podcast feed'
+                'get:Download a specific episode from a podcast'
+                'backfill:Download archived episodes from a podcast'
                 'update-feed:Check and download new episodes from a specific podcast'
                 'init-config:Generate an example config file'
-                'clean:Remove all downloaded MP3 files from configured podcasts'
+                'clean:Remove all downloaded episode files from configured podcasts'
                 'pause:Pause a podcast so it is skipped during download'
                 'unpause:Unpause a podcast so it resumes downloading'
                 'completions:Generate shell completion scripts'
@@ -479,17 +1504,43 @@ _pg() {
             ;;
         args)
             case $words[1] in
-                update-feed|pause|unpause)
+                update-feed)
+                    _arguments \
+                        '1:podcast:(${(f)"$(pg names 2>/dev/null)"})' \
+                        '--fail-fast[Stop at the first failed episode]'
+                    ;;
+                pause|unpause)
                     local names=(${(f)"$(pg names 2>/dev/null)"})
                     _describe 'podcast' names
                     ;;
+                backfill)
+                    _arguments \
+                        '1:podcast:(${(f)"$(pg names 2>/dev/null)"})' \
+                        '--since[Download episodes published on or after date]:date'
+                    ;;
+                get)
+                    _arguments \
+                        '1:podcast:(${(f)"$(pg names 2>/dev/null)"})' \
+                        '2:selector'
+                    ;;
+                episodes)
+                    _arguments \
+                        '1:podcast:(${(f)"$(pg names 2>/dev/null)"})' \
+                        '(-s --search)'{-s,--search}'[Only show episodes whose title contains text]:text' \
+                        '--since[Only show episodes published on or after date]:date' \
+                        '(-l --limit)'{-l,--limit}'[Maximum number of episodes to show]:count' \
+                        '--cached[Use the cached feed instead of fetching it]' \
+                        '--explain[Show why each episode would be kept or skipped]' \
+                        '--json[Output as JSON]'
+                    ;;
                 completions)
                     local shells=('bash' 'zsh' 'fish')
                     _describe 'shell' shells
                     ;;
                 download)
                     _arguments \
-                        '(-m --max-episodes)'{-m,--max-episodes}'[Maximum episodes per podcast]:count'
+                        '(-m --max-episodes)'{-m,--max-episodes}'[Maximum episodes per podcast]:count' \
+                        '--fail-fast[Stop at the first failed feed or episode]'
                     ;;
                 add)
                     _arguments \
@@ -514,21 +1565,34 @@ complete -c pg -n '__fish_use_subcommand' -a download -d 'Download new episodes
 complete -c pg -n '__fish_use_subcommand' -a add -d 'Add a new podcast to the config'
 complete -c pg -n '__fish_use_subcommand' -a list -d 'List all configured podcasts'
 complete -c pg -n '__fish_use_subcommand' -a status -d 'Show last-check timestamps for all podcasts'
+complete -c pg -n '__fish_use_subcommand' -a episodes -d 'List the episodes in a podcast feed'
+complete -c pg -n '__fish_use_subcommand' -a get -d 'Download a specific episode from a podcast'
+complete -c pg -n '__fish_use_subcommand' -a backfill -d 'Download archived episodes from a podcast'
 complete -c pg -n '__fish_use_subcommand' -a update-feed -d 'Check and download new episodes from a specific podcast'
 complete -c pg -n '__fish_use_subcommand' -a init-config -d 'Generate an example config file'
-complete -c pg -n '__fish_use_subcommand' -a clean -d 'Remove all downloaded MP3 files from configured podcasts'
+complete -c pg -n '__fish_use_subcommand' -a clean -d 'Remove all downloaded episode files from configured podcasts'
 complete -c pg -n '__fish_use_subcommand' -a pause -d 'Pause a podcast so it is skipped during download'
 complete -c pg -n '__fish_use_subcommand' -a unpause -d 'Unpause a podcast so it resumes downloading'
 complete -c pg -n '__fish_use_subcommand' -a completions -d 'Generate shell completion scripts'
 
-complete -c pg -n '__fish_seen_subcommand_from update-feed pause unpause' -a '(__pg_podcast_names)'
+complete -c pg -n '__fish_seen_subcommand_from update-feed pause unpause episodes get backfill' -a '(__pg_podcast_names)'
 complete -c pg -n '__fish_seen_subcommand_from completions' -a 'bash zsh fish'
 
 complete -c pg -s d -l debug -d 'Enable debug logging'
+complete -c pg -l output -d 'Output format' -xa 'table json ndjson'
 complete -c pg -n '__fish_seen_subcommand_from download' -s m -l max-episodes -d 'Maximum episodes per podcast' -r
+complete -c pg -n '__fish_seen_subcommand_from download' -l fail-fast -d 'Stop at the first failed feed or episode'
+complete -c pg -n '__fish_seen_subcommand_from update-feed' -l fail-fast -d 'Stop at the first failed episode'
 complete -c pg -n '__fish_seen_subcommand_from add' -s n -l name -d 'Name for the podcast' -r
 complete -c pg -n '__fish_seen_subcommand_from add' -s o -l output-dir -d 'Output directory' -r
 complete -c pg -n '__fish_seen_subcommand_from pause unpause' -l all -d 'Apply to all podcasts'
+complete -c pg -n '__fish_seen_subcommand_from episodes' -s s -l search -d 'Only show episodes whose title contains text' -r
+complete -c pg -n '__fish_seen_subcommand_from episodes' -l since -d 'Only show episodes published on or after date' -r
+complete -c pg -n '__fish_seen_subcommand_from episodes' -s l -l limit -d 'Maximum number of episodes to show' -r
+complete -c pg -n '__fish_seen_subcommand_from episodes' -l cached -d 'Use the cached feed instead of fetching it'
+complete -c pg -n '__fish_seen_subcommand_from episodes' -l explain -d 'Show why each episode would be kept or skipped'
+complete -c pg -n '__fish_seen_subcommand_from episodes' -l json -d 'Output as JSON'
+complete -c pg -n '__fish_seen_subcommand_from backfill' -l since -d 'Download episodes published on or after date' -r
 "#;
 
 pub fn pause_podcast(name: Option<String>, all: bool) -> Result<()> {
@@ -550,11 +1614,7 @@ pub fn pause_podcast(name: Option<String>, all: bool) -> Result<()> {
         println!("Paused {} podcast{}", count, if count == 1 { "" } else { "s" });
     } else {
         let name = name.unwrap();
-        let podcast = config
-            .podcasts
-            .iter_mut()
-            .find(|p| p.name == name)
-            .ok_or_else(|| anyhow::anyhow!("Podcast '{}' not found in config", name))?;
+        let podcast = config.podcast_mut(&name)?;
 
         if podcast.paused {
             println!("Podcast '{}' is already paused", name);
@@ -587,11 +1647,7 @@ pub fn unpause_podcast(name: Option<String>, all: bool) -> Result<()> {
         println!("Unpaused {} podcast{}", count, if count == 1 { "" } else { "s" });
     } else {
         let name = name.unwrap();
-        let podcast = config
-            .podcasts
-            .iter_mut()
-            .find(|p| p.name == name)
-            .ok_or_else(|| anyhow::anyhow!("Podcast '{}' not found in config", name))?;
+        let podcast = config.podcast_mut(&name)?;
 
         if !podcast.paused {
             println!("Podcast '{}' is not paused", name);
@@ -604,3 +1660,154 @@ pub fn unpause_podcast(name: Option<String>, all: bool) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn episode(title: &str, pub_date: DateTime<Utc>) -> feed::Episode {
+        feed::Episode {
+            guid: Some(title.to_string()),
+            title: title.to_string(),
+            description: None,
+            url: format!("https://example.com/{}.mp3", title),
+            pub_date,
+            pub_date_known: true,
+            duration: None,
+            size: None,
+            season: None,
+            episode_number: None,
+            episode_type: None,
+            mime_type: None,
+            enclosures: Vec::new(),
+            chapters_url: None,
+            transcripts: Vec::new(),
+            image_url: None,
+        }
+    }
+
+    fn titles(episodes: &[feed::Episode]) -> Vec<&str> {
+        episodes.iter().map(|e| e.title.as_str()).collect()
+    }
+
+    #[test]
+    fn sync_after_an_empty_first_sync_picks_up_new_episodes() {
+        let podcast = PodcastConfig {
+            name: "Show".to_string(),
+            initial_backfill: Some(Backfill::None),
+            ..Default::default()
+        };
+        let config = Config::default();
+        let mut state = State::default();
+        let first_sync = Utc::now();
+        let feed = vec![
+            episode("old", first_sync - Duration::days(7)),
+            episode("older", first_sync - Duration::days(14)),
+        ];
+
+        let last_check = state.get_last_check(&podcast.name);
+        let selection = select_episodes(&podcast, &config, feed.clone(), last_check, None).unwrap();
+        assert!(selection.episodes.is_empty());
+        assert!(selection.skipped.is_empty());
+        assert_eq!(selection.checked_until(None, first_sync), Some(first_sync));
+        state.set_last_check(podcast.name.clone(), first_sync);
+
+        // With the first check recorded, the next sync takes what was
+        // published since rather than backfilling again
+        let mut feed = feed;
+        feed.push(episode("new", first_sync + Duration::hours(1)));
+        let last_check = state.get_last_check(&podcast.name);
+        let selection = select_episodes(&podcast, &config, feed, last_check, None).unwrap();
+        assert_eq!(titles(&selection.episodes), ["new"]);
+    }
+
+    #[test]
+    fn oldest_order_continues_where_the_last_sync_stopped() {
+        let podcast = PodcastConfig {
+            name: "Serial".to_string(),
+            order: Some(EpisodeOrder::Oldest),
+            max_episodes: Some(2),
+            ..Default::default()
+        };
+        let config = Config::default();
+        let mut state = State::default();
+        let date = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let feed: Vec<_> = (1..=5)
+            .rev()
+            .map(|n| episode(&format!("part {}", n), date + Duration::days(n)))
+            .collect();
+
+        for expected in [["part 1", "part 2"], ["part 3", "part 4"]] {
+            let last_check = state.get_last_check(&podcast.name);
+            let selection = select_episodes(&podcast, &config, feed.clone(), last_check, None).unwrap();
+            assert_eq!(titles(&selection.episodes), expected);
+            assert!(selection.skipped.is_empty());
+
+            let last_downloaded = selection.episodes.last().map(|e| e.pub_date);
+            let checked = selection.checked_until(last_downloaded, Utc::now()).unwrap();
+            state.set_last_check(podcast.name.clone(), checked);
+        }
+
+        let last_check = state.get_last_check(&podcast.name);
+        let selection = select_episodes(&podcast, &config, feed, last_check, None).unwrap();
+        assert_eq!(titles(&selection.episodes), ["part 5"]);
+        assert!(!selection.held_back);
+    }
+
+    #[test]
+    fn oldest_order_keeps_the_last_check_when_nothing_downloaded() {
+        let podcast = PodcastConfig {
+            order: Some(EpisodeOrder::Oldest),
+            max_episodes: Some(1),
+            ..Default::default()
+        };
+        let date = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let feed = vec![episode("a", date), episode("b", date + Duration::days(1))];
+
+        let selection = select_episodes(&podcast, &Config::default(), feed, None, None).unwrap();
+        assert!(selection.held_back);
+        assert_eq!(selection.checked_until(None, Utc::now()), None);
+    }
+
+    #[test]
+    fn video_episodes_are_not_processed() {
+        let podcast = PodcastConfig {
+            processing: crate::config::ProcessingConfig {
+                mono: Some(true),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let config = Config::default();
+        let date = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let audio = feed::Episode {
+            mime_type: Some("audio/mpeg".to_string()),
+            ..episode("audio", date)
+        };
+        let video = feed::Episode {
+            mime_type: Some("video/mp4".to_string()),
+            ..episode("video", date)
+        };
+
+        assert!(processing_for(&podcast, &config, &audio).is_some_and(|s| s.mono));
+        assert!(processing_for(&podcast, &config, &video).is_none());
+    }
+
+    #[test]
+    fn newest_order_skips_episodes_past_the_limit() {
+        let podcast = PodcastConfig {
+            name: "Show".to_string(),
+            max_episodes: Some(1),
+            ..Default::default()
+        };
+        let date = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let feed = vec![episode("a", date), episode("b", date + Duration::days(1))];
+
+        let selection = select_episodes(&podcast, &Config::default(), feed, None, None).unwrap();
+        assert_eq!(titles(&selection.episodes), ["b"]);
+        assert_eq!(selection.skipped.len(), 1);
+        assert_eq!(selection.skipped[0].0.title, "a");
+        assert_eq!(selection.skipped[0].1, "over the limit of 1 episodes");
+    }
+}
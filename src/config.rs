@@ -1,16 +1,85 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::error::PgError;
+use crate::filter::EpisodeFilter;
+use crate::tagger;
+use crate::secrets::SecretRef;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Config {
     #[serde(default)]
     pub base_dir: Option<PathBuf>,
+    /// Default backfill for podcasts that have never been checked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial_backfill: Option<Backfill>,
+    #[serde(default, skip_serializing_if = "HttpConfig::is_empty")]
+    pub http: HttpConfig,
+    /// How downloaded episodes are tagged, natively by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tagger: Option<TaggerBackend>,
+    /// Templates for each tag, for every podcast
+    #[serde(default, skip_serializing_if = "TagMapping::is_empty")]
+    pub tags: TagMapping,
+    /// Transcript settings for every podcast
+    #[serde(flatten)]
+    pub transcripts: TranscriptConfig,
+    /// How cover art is prepared for embedding
+    #[serde(flatten)]
+    pub cover: CoverConfig,
+    /// Name of the podcast artwork saved in each output directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cover_filename: Option<CoverFilename>,
+    /// ffmpeg post-processing for every podcast
+    #[serde(default, skip_serializing_if = "ProcessingConfig::is_empty")]
+    pub processing: ProcessingConfig,
+    /// Measure each episode's ReplayGain and write it to its tags
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaygain: Option<bool>,
     pub podcasts: Vec<PodcastConfig>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Settings for the HTTP client shared by every request
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct HttpConfig {
+    /// User-Agent header, defaults to `podcast-getter/<version>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Seconds to wait for a connection to be established
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u64>,
+    /// Seconds to wait for a response, or for more data while reading one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_timeout: Option<u64>,
+    /// Proxy URL for all requests, e.g. `http://proxy:8080` or `socks5://host:1080`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// How many times to retry timeouts, connection errors, 429 and 5xx responses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Extra headers sent with every request
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl HttpConfig {
+    fn is_empty(&self) -> bool {
+        self.retries.is_none()
+            && self.user_agent.is_none()
+            && self.connect_timeout.is_none()
+            && self.read_timeout.is_none()
+            && self.proxy.is_none()
+            && self.headers.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct PodcastConfig {
     pub name: String,
     pub url: String,
@@ -20,6 +89,529 @@ pub struct PodcastConfig {
     pub max_episodes: Option<usize>,
     #[serde(default)]
     pub paused: bool,
+    /// Which episodes to download the first time this podcast is checked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial_backfill: Option<Backfill>,
+    /// Whether `max_episodes` keeps the newest or the oldest new episodes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<EpisodeOrder>,
+    #[serde(flatten)]
+    pub filters: FilterConfig,
+    #[serde(flatten)]
+    pub enclosure: EnclosurePreference,
+    /// Extra headers for this podcast's requests, overriding `[http]` headers
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// Credentials for private feeds, sent with feed, episode and image
+    /// requests to the feed's own host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthConfig>,
+    /// Other hosts the credentials are sent to, such as the feed's media CDN
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auth_hosts: Vec<String>,
+    /// Tag templates for this podcast, overriding the top-level `[tags]`
+    #[serde(default, skip_serializing_if = "TagMapping::is_empty")]
+    pub tags: TagMapping,
+    /// Transcript settings for this podcast, overriding the top-level ones
+    #[serde(flatten)]
+    pub transcripts: TranscriptConfig,
+    /// Post-processing for this podcast, overriding the top-level `[processing]`
+    #[serde(default, skip_serializing_if = "ProcessingConfig::is_empty")]
+    pub processing: ProcessingConfig,
+    /// Override the top-level `replaygain` for this podcast
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaygain: Option<bool>,
+}
+
+/// Templates for the tags written to downloaded episodes. Placeholders like
+/// `{title}` are filled in from the feed; an empty template leaves the tag
+/// unset.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct TagMapping {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album_artist: Option<String>,
+    /// Release date, as `YYYY-MM-DD` or a year
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disc: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub podcast_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub episode_id: Option<String>,
+}
+
+impl TagMapping {
+    fn is_empty(&self) -> bool {
+        self.templates().iter().all(|(_, template)| template.is_none())
+    }
+
+    /// Each tag's setting name and template
+    fn templates(&self) -> [(&'static str, &Option<String>); 11] {
+        [
+            ("title", &self.title),
+            ("album", &self.album),
+            ("artist", &self.artist),
+            ("album_artist", &self.album_artist),
+            ("date", &self.date),
+            ("track", &self.track),
+            ("disc", &self.disc),
+            ("genre", &self.genre),
+            ("comment", &self.comment),
+            ("podcast_url", &self.podcast_url),
+            ("episode_id", &self.episode_id),
+        ]
+    }
+
+    /// Check every template only uses known placeholders
+    fn validate(&self) -> Result<(), PgError> {
+        for (name, template) in self.templates() {
+            if let Some(template) = template {
+                tagger::check_template(template)
+                    .map_err(|e| PgError::config(format_args!("Invalid `tags.{}`: {}", name, e)))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill unset templates from `fallback`, then from the built-in defaults
+    fn with_fallback(&self, fallback: &TagMapping) -> TagMapping {
+        let pick = |own: &Option<String>, fallback: &Option<String>, default: &str| {
+            Some(own.as_ref().or(fallback.as_ref()).map_or(default, String::as_str).to_string())
+        };
+        TagMapping {
+            title: pick(&self.title, &fallback.title, "{date} {title}"),
+            album: pick(&self.album, &fallback.album, "{podcast}"),
+            artist: pick(&self.artist, &fallback.artist, "{podcast}"),
+            album_artist: pick(&self.album_artist, &fallback.album_artist, "{author}"),
+            date: pick(&self.date, &fallback.date, "{date}"),
+            track: pick(&self.track, &fallback.track, "{episode}"),
+            disc: pick(&self.disc, &fallback.disc, "{season}"),
+            genre: pick(&self.genre, &fallback.genre, "{category}"),
+            comment: pick(&self.comment, &fallback.comment, "{description}"),
+            podcast_url: pick(&self.podcast_url, &fallback.podcast_url, "{feed_url}"),
+            episode_id: pick(&self.episode_id, &fallback.episode_id, "{guid}"),
+        }
+    }
+}
+
+/// How to authenticate to a private feed. Secrets are references to
+/// environment variables or secrets.toml entries, never inline values.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AuthConfig {
+    /// HTTP Basic auth
+    Basic { username: String, password: SecretRef },
+    /// `Authorization: Bearer <token>`
+    Bearer { token: SecretRef },
+    /// A `Cookie` header, e.g. a session cookie copied from the browser
+    Cookie { cookie: SecretRef },
+}
+
+impl fmt::Display for AuthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthConfig::Basic { username, password } => {
+                write!(f, "basic (user '{}', password from {})", username, password)
+            }
+            AuthConfig::Bearer { token } => write!(f, "bearer (token from {})", token),
+            AuthConfig::Cookie { cookie } => write!(f, "cookie (from {})", cookie),
+        }
+    }
+}
+
+/// Per-podcast preferences for picking among an item's media files
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct EnclosurePreference {
+    /// MIME types in order of preference, e.g. `["audio/opus", "audio/mpeg"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prefer: Vec<String>,
+    /// Prefer the highest or lowest bitrate/resolution available
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefer_quality: Option<Quality>,
+    /// Prefer audio or video when a feed offers both
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefer_media: Option<MediaKind>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quality {
+    High,
+    Low,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Audio,
+    Video,
+}
+
+/// Per-podcast rules for which new episodes to download
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct FilterConfig {
+    /// Only download episodes whose title matches this regex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include_title: Option<String>,
+    /// Skip episodes whose title matches this regex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude_title: Option<String>,
+    /// Skip episodes with these `itunes:episodeType` values, e.g. "trailer" or "bonus"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_episode_types: Vec<String>,
+    /// Skip episodes shorter than this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_duration: Option<DurationSetting>,
+    /// Skip episodes longer than this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_duration: Option<DurationSetting>,
+    /// Only download enclosures with these MIME types; `audio/*` matches any audio type
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mime_types: Vec<String>,
+}
+
+/// A duration given in config as seconds (`600`) or as `"MM:SS"` / `"HH:MM:SS"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawDuration", into = "u64")]
+pub struct DurationSetting(pub u64);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawDuration {
+    Seconds(u64),
+    Text(String),
+}
+
+impl TryFrom<RawDuration> for DurationSetting {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: RawDuration) -> Result<Self> {
+        match raw {
+            RawDuration::Seconds(secs) => Ok(DurationSetting(secs)),
+            RawDuration::Text(text) => crate::feed::parse_duration(&text)
+                .map(DurationSetting)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Invalid duration '{}': expected seconds, MM:SS or HH:MM:SS", text)
+                }),
+        }
+    }
+}
+
+impl From<DurationSetting> for u64 {
+    fn from(duration: DurationSetting) -> Self {
+        duration.0
+    }
+}
+
+/// Order in which new episodes are picked and downloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EpisodeOrder {
+    /// Newest first, for shows where only recent episodes matter
+    #[default]
+    Newest,
+    /// Oldest first, for serialized shows meant to be heard in order
+    Oldest,
+}
+
+/// How cover art is resized and re-encoded before it is embedded. The
+/// downloaded artwork itself is kept as it is.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct CoverConfig {
+    /// Largest width or height, in pixels, of embedded cover art
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cover_max_size: Option<u32>,
+    /// Image format to embed cover art as
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cover_format: Option<CoverFormat>,
+    /// JPEG quality, from 1 to 100
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cover_quality: Option<u8>,
+    /// Crop cover art to a square around its center
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cover_square: Option<bool>,
+}
+
+impl CoverConfig {
+    /// Whether cover art is embedded as downloaded
+    pub fn is_empty(&self) -> bool {
+        self.cover_max_size.is_none() && self.cover_format.is_none() && !self.cover_square.unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverFormat {
+    Jpeg,
+    Png,
+}
+
+/// Name of a podcast's saved artwork, such as `folder.jpg`, where `{name}`
+/// stands for the podcast's name. The extension picks the image format.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CoverFilename(String);
+
+impl CoverFilename {
+    /// The file name for a podcast
+    pub fn render(&self, podcast_name: &str) -> String {
+        self.0.replace("{name}", &sanitize_name(podcast_name))
+    }
+
+    /// The name artwork was saved under before it was configurable, which
+    /// dropped spaces from the podcast's name
+    pub fn legacy_name(podcast_name: &str) -> String {
+        format!("{}_cover.png", sanitize_name(podcast_name).replace(' ', ""))
+    }
+
+    /// The image format the artwork is saved in
+    pub fn format(&self) -> CoverFormat {
+        if self.0.to_lowercase().ends_with(".png") {
+            CoverFormat::Png
+        } else {
+            CoverFormat::Jpeg
+        }
+    }
+}
+
+impl Default for CoverFilename {
+    fn default() -> Self {
+        CoverFilename("{name}_cover.png".to_string())
+    }
+}
+
+impl FromStr for CoverFilename {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.contains(['/', '\\']) || s.starts_with('.') {
+            anyhow::bail!("Invalid cover_filename '{}': expected a file name, not a path", s);
+        }
+        let extension = s.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+        if !matches!(extension.as_deref(), Some("jpg" | "jpeg" | "png")) {
+            anyhow::bail!("Invalid cover_filename '{}': expected a .jpg, .jpeg or .png file", s);
+        }
+        Ok(CoverFilename(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for CoverFilename {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<CoverFilename> for String {
+    fn from(filename: CoverFilename) -> Self {
+        filename.0
+    }
+}
+
+/// How downloaded episodes are tagged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TaggerBackend {
+    /// Write ID3, MP4 and Vorbis comment tags in-process
+    #[default]
+    Native,
+    /// Run the external `audio-metadata` binary
+    AudioMetadata,
+    /// Leave files untagged
+    None,
+}
+
+/// Whether and how transcripts linked with `podcast:transcript` are saved
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct TranscriptConfig {
+    /// Download each episode's transcript, off by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcripts: Option<bool>,
+    /// Format to save transcripts in, converting if the feed doesn't offer
+    /// it. By default they are saved as published.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcript_format: Option<TranscriptFormat>,
+    /// Also embed the transcript's text in the file's lyrics tag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcript_lyrics: Option<bool>,
+}
+
+/// Transcript settings for one podcast, once enabled
+#[derive(Debug, Clone, Copy)]
+pub struct TranscriptSettings {
+    pub format: Option<TranscriptFormat>,
+    pub lyrics: bool,
+}
+
+/// A transcript format `pg` can convert to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscriptFormat {
+    Srt,
+    Vtt,
+    /// Plain text, without timings
+    Txt,
+}
+
+/// How downloaded episodes are run through ffmpeg before they are tagged
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ProcessingConfig {
+    /// Normalize loudness with ffmpeg's EBU R128 `loudnorm` filter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loudnorm: Option<bool>,
+    /// Integrated loudness to normalize to, in LUFS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_loudness: Option<f64>,
+    /// Maximum true peak, in dBTP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub true_peak: Option<f64>,
+    /// Downmix to a single channel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mono: Option<bool>,
+    /// Audio bitrate to encode at, e.g. `64k`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bitrate: Option<String>,
+    /// Format to transcode to. By default the downloaded format is kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<AudioFormat>,
+    /// Keep the downloaded file next to the processed one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_original: Option<bool>,
+    /// ffmpeg binary to run, `ffmpeg` from PATH by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ffmpeg: Option<PathBuf>,
+}
+
+impl ProcessingConfig {
+    fn is_empty(&self) -> bool {
+        self.loudnorm.is_none()
+            && self.target_loudness.is_none()
+            && self.true_peak.is_none()
+            && self.mono.is_none()
+            && self.bitrate.is_none()
+            && self.format.is_none()
+            && self.keep_original.is_none()
+            && self.ffmpeg.is_none()
+    }
+}
+
+/// Post-processing for one podcast, once there is something to do
+#[derive(Debug, Clone)]
+pub struct ProcessingSettings {
+    pub loudnorm: Option<Loudnorm>,
+    pub mono: bool,
+    pub bitrate: Option<String>,
+    pub format: Option<AudioFormat>,
+    pub keep_original: bool,
+    pub ffmpeg: PathBuf,
+}
+
+/// EBU R128 loudness normalization targets
+#[derive(Debug, Clone, Copy)]
+pub struct Loudnorm {
+    /// Integrated loudness, in LUFS
+    pub target: f64,
+    /// Maximum true peak, in dBTP
+    pub true_peak: f64,
+}
+
+/// An audio format ffmpeg can transcode episodes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+    Mp3,
+    M4a,
+    Opus,
+    Ogg,
+    Flac,
+}
+
+impl AudioFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::M4a => "m4a",
+            AudioFormat::Opus => "opus",
+            AudioFormat::Ogg => "ogg",
+            AudioFormat::Flac => "flac",
+        }
+    }
+}
+
+/// Which existing episodes to download on a podcast's first sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Backfill {
+    /// Download nothing, only episodes published from now on
+    None,
+    /// Download the newest N episodes
+    Latest(usize),
+    /// Download episodes published on or after a date
+    Since(NaiveDate),
+    /// Download the whole back catalog
+    #[default]
+    All,
+}
+
+impl FromStr for Backfill {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            None if s == "none" => Ok(Backfill::None),
+            None if s == "all" => Ok(Backfill::All),
+            Some(("latest", n)) => n
+                .parse()
+                .map(Backfill::Latest)
+                .with_context(|| format!("Invalid episode count in backfill '{}'", s)),
+            Some(("since", date)) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map(Backfill::Since)
+                .with_context(|| format!("Invalid date in backfill '{}', expected YYYY-MM-DD", s)),
+            _ => anyhow::bail!(
+                "Invalid backfill '{}': expected 'none', 'latest:N', 'since:YYYY-MM-DD' or 'all'",
+                s
+            ),
+        }
+    }
+}
+
+impl TryFrom<String> for Backfill {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Backfill> for String {
+    fn from(backfill: Backfill) -> Self {
+        backfill.to_string()
+    }
+}
+
+impl fmt::Display for Backfill {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backfill::None => write!(f, "none"),
+            Backfill::Latest(n) => write!(f, "latest:{}", n),
+            Backfill::Since(date) => write!(f, "since:{}", date.format("%Y-%m-%d")),
+            Backfill::All => write!(f, "all"),
+        }
+    }
 }
 
 impl PodcastConfig {
@@ -38,12 +630,89 @@ impl PodcastConfig {
                 self.name
             )
         })?;
-        Ok(base.join(sanitize_dir_name(&self.name)))
+        Ok(base.join(sanitize_name(&self.name)))
+    }
+
+    /// Resolve the backfill to use on this podcast's first sync, falling back
+    /// to the top-level `initial_backfill`
+    pub fn resolved_backfill(&self, default: Option<Backfill>) -> Backfill {
+        self.initial_backfill.or(default).unwrap_or_default()
+    }
+
+    /// Whether to measure this podcast's ReplayGain, falling back to the
+    /// top-level `replaygain`
+    pub fn resolved_replaygain(&self, default: Option<bool>) -> bool {
+        self.replaygain.or(default).unwrap_or(false)
     }
+
+    /// Resolve this podcast's tag templates, falling back to the top-level
+    /// `[tags]` and then the defaults
+    pub fn resolved_tags(&self, global: &TagMapping) -> TagMapping {
+        let mut tags = self.tags.with_fallback(global);
+        // A private feed's URL can carry its own credentials, so it's only
+        // written into files when the podcast asks for it
+        if self.auth.is_some() && self.tags.podcast_url.is_none() {
+            tags.podcast_url = Some(String::new());
+        }
+        tags
+    }
+
+    /// Resolve this podcast's transcript settings against the top-level
+    /// ones. Returns `None` when transcripts are off.
+    pub fn resolved_transcripts(&self, global: &TranscriptConfig) -> Option<TranscriptSettings> {
+        let own = &self.transcripts;
+        if !own.transcripts.or(global.transcripts).unwrap_or(false) {
+            return None;
+        }
+        Some(TranscriptSettings {
+            format: own.transcript_format.or(global.transcript_format),
+            lyrics: own.transcript_lyrics.or(global.transcript_lyrics).unwrap_or(false),
+        })
+    }
+
+    /// Resolve this podcast's post-processing against the top-level
+    /// settings. Returns `None` when there is nothing to do.
+    pub fn resolved_processing(&self, global: &ProcessingConfig) -> Option<ProcessingSettings> {
+        let own = &self.processing;
+        let loudnorm = own.loudnorm.or(global.loudnorm).unwrap_or(false).then(|| Loudnorm {
+            // Common targets for spoken word podcasts
+            target: own.target_loudness.or(global.target_loudness).unwrap_or(-16.0),
+            true_peak: own.true_peak.or(global.true_peak).unwrap_or(-1.5),
+        });
+        let settings = ProcessingSettings {
+            loudnorm,
+            mono: own.mono.or(global.mono).unwrap_or(false),
+            bitrate: own.bitrate.clone().or_else(|| global.bitrate.clone()),
+            format: own.format.or(global.format),
+            keep_original: own.keep_original.or(global.keep_original).unwrap_or(false),
+            ffmpeg: own
+                .ffmpeg
+                .clone()
+                .or_else(|| global.ffmpeg.clone())
+                .unwrap_or_else(|| PathBuf::from("ffmpeg")),
+        };
+        let has_work = settings.loudnorm.is_some()
+            || settings.mono
+            || settings.bitrate.is_some()
+            || settings.format.is_some();
+        has_work.then_some(settings)
+    }
+
+    /// Path of the cached copy of this podcast's feed
+    pub fn feed_cache_file(&self) -> Result<PathBuf> {
+        Ok(Config::cache_dir()?
+            .join("feeds")
+            .join(format!("{}.xml", sanitize_name(&self.name))))
+    }
+}
+
+fn not_in_config(name: &str) -> PgError {
+    PgError::config(format_args!("Podcast '{}' not found in config", name))
 }
 
-/// Sanitize a podcast name into a safe directory component.
-fn sanitize_dir_name(name: &str) -> String {
+/// Sanitize a name into a safe file or directory name, keeping letters,
+/// digits, spaces, `-` and `_`
+pub fn sanitize_name(name: &str) -> String {
     let sanitized: String = name
         .chars()
         .filter(|c| c.is_alphanumeric() || *c == ' ' || *c == '-' || *c == '_')
@@ -60,6 +729,14 @@ impl Config {
         Ok(config_dir)
     }
 
+    /// Get the cache directory path (~/.cache/podcast-getter)
+    pub fn cache_dir() -> Result<PathBuf> {
+        let cache_dir = dirs::cache_dir()
+            .context("Could not determine cache directory")?
+            .join("podcast-getter");
+        Ok(cache_dir)
+    }
+
     /// Get the config file path
     pub fn config_file() -> Result<PathBuf> {
         Ok(Self::config_dir()?.join("config.toml"))
@@ -70,21 +747,52 @@ impl Config {
         let config_path = Self::config_file()?;
 
         if !config_path.exists() {
-            anyhow::bail!(
+            return Err(PgError::Config(format!(
                 "Config file not found at {}. Please create it first.",
                 config_path.display()
-            );
+            ))
+            .into());
         }
 
         let content = fs::read_to_string(&config_path)
-            .context("Failed to read config file")?;
+            .map_err(|e| PgError::config(format_args!("Failed to read config file: {}", e)))?;
 
         let config: Config = toml::from_str(&content)
-            .context("Failed to parse config file")?;
+            .map_err(|e| PgError::config(format_args!("Failed to parse config file: {}", e)))?;
+        config.validate()?;
 
         Ok(config)
     }
 
+    /// Check the settings serde can't, so a mistake stops the run before
+    /// anything is fetched rather than failing one podcast partway through
+    fn validate(&self) -> Result<(), PgError> {
+        self.tags.validate()?;
+        for podcast in &self.podcasts {
+            let in_podcast =
+                |e: &dyn fmt::Display| PgError::config(format_args!("Podcast '{}': {:#}", podcast.name, e));
+            EpisodeFilter::new(&podcast.filters).map_err(|e| in_podcast(&e))?;
+            podcast.tags.validate().map_err(|e| in_podcast(&e))?;
+        }
+        Ok(())
+    }
+
+    /// The podcast with this name. An unknown name is a config error.
+    pub fn podcast(&self, name: &str) -> Result<&PodcastConfig, PgError> {
+        self.podcasts
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| not_in_config(name))
+    }
+
+    /// Like [`Config::podcast`], for changing the podcast's settings
+    pub fn podcast_mut(&mut self, name: &str) -> Result<&mut PodcastConfig, PgError> {
+        self.podcasts
+            .iter_mut()
+            .find(|p| p.name == name)
+            .ok_or_else(|| not_in_config(name))
+    }
+
     /// Save config to file
     pub fn save(&self) -> Result<()> {
         let config_dir = Self::config_dir()?;
@@ -116,13 +824,13 @@ impl Config {
                     .context("Could not determine download directory")?
                     .join("podcasts"),
             ),
+            initial_backfill: Some(Backfill::Latest(3)),
             podcasts: vec![PodcastConfig {
                 name: "Example Podcast".to_string(),
                 url: "https://example.com/feed.xml".to_string(),
-                output_dir: None,
-                max_episodes: None,
-                paused: false,
+                ..Default::default()
             }],
+            ..Default::default()
         };
 
         let content = toml::to_string_pretty(&example_config)
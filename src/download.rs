@@ -1,50 +1,166 @@
-use anyhow::{Context, Result};
 use std::path::Path;
 
-/// Download a file from a URL and save it to disk
-pub async fn download_file(url: &str, output_path: &Path) -> Result<()> {
+use crate::config::sanitize_name;
+use crate::error::PgError;
+use crate::http::HttpClient;
+
+/// File extensions `pg` treats as downloaded episode media
+pub const MEDIA_EXTENSIONS: &[&str] = &[
+    "mp3", "m4a", "m4b", "aac", "ogg", "oga", "opus", "flac", "wav", "mp4", "m4v", "mov",
+];
+
+/// Download a media file from a URL and save it to disk.
+///
+/// The response is checked before anything is written: the `Content-Type`
+/// must not be a web page or document, the body must be as long as the
+/// server's `Content-Length` and roughly as long as the feed advertised
+/// (`expected_size`), and it must start like a known audio or video format.
+pub async fn download_file(
+    http: &HttpClient,
+    url: &str,
+    output_path: &Path,
+    expected_size: Option<u64>,
+) -> Result<(), PgError> {
     // Create parent directories if they don't exist
     if let Some(parent) = output_path.parent() {
         std::fs::create_dir_all(parent)
-            .context("Failed to create output directory")?;
+            .map_err(|e| PgError::filesystem("create output directory", parent, e))?;
     }
 
-    let response = reqwest::get(url)
-        .await
-        .context("Failed to fetch file")?;
+    let fetched = http.fetch(url).await?;
+
+    validate_media(
+        &fetched.body,
+        fetched.content_type.as_deref(),
+        fetched.content_length,
+        expected_size,
+    )?;
 
-    if !response.status().is_success() {
-        anyhow::bail!(
-            "Failed to download file: HTTP {}",
-            response.status()
-        );
+    std::fs::write(output_path, fetched.body)
+        .map_err(|e| PgError::filesystem("write", output_path, e))?;
+
+    Ok(())
+}
+
+/// Check that a downloaded body is plausibly the media file we asked for
+fn validate_media(
+    bytes: &[u8],
+    content_type: Option<&str>,
+    content_length: Option<u64>,
+    expected_size: Option<u64>,
+) -> Result<(), PgError> {
+    if let Some(content_type) = content_type {
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        if mime.starts_with("text/")
+            || mime.contains("html")
+            || mime.contains("json")
+            || mime.contains("xml")
+        {
+            return Err(PgError::InvalidMedia(format!(
+                "server returned '{}'",
+                mime
+            )));
+        }
     }
 
-    let bytes = response
-        .bytes()
-        .await
-        .context("Failed to read response body")?;
+    let received = bytes.len() as u64;
 
-    std::fs::write(output_path, bytes)
-        .context("Failed to write file to disk")?;
+    if let Some(length) = content_length {
+        if received != length {
+            return Err(PgError::InvalidMedia(format!(
+                "truncated, received {} of {} bytes",
+                received, length
+            )));
+        }
+    }
+
+    // Feeds often round or misreport enclosure lengths, so only a body far
+    // smaller than advertised is treated as a failed download
+    if let Some(expected) = expected_size {
+        if received < expected / 2 {
+            return Err(PgError::InvalidMedia(format!(
+                "too small, received {} bytes but the feed lists {}",
+                received, expected
+            )));
+        }
+        if received != expected {
+            tracing::debug!(
+                "Received {} bytes, feed lists {}",
+                received,
+                expected
+            );
+        }
+    }
+
+    if !looks_like_media(bytes) {
+        return Err(PgError::InvalidMedia(
+            "content does not look like audio or video".to_string(),
+        ));
+    }
 
     Ok(())
 }
 
+/// Sniff the magic bytes of common podcast audio and video formats
+fn looks_like_media(bytes: &[u8]) -> bool {
+    match bytes {
+        // MP3 with an ID3v2 tag
+        [b'I', b'D', b'3', ..] => true,
+        // MPEG audio or ADTS AAC frame sync
+        [0xFF, b, ..] if b & 0xE0 == 0xE0 => true,
+        // MP4 / M4A / MOV
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => true,
+        // Older QuickTime files start straight with one of these atoms
+        [_, _, _, _, b'm', b'o', b'o', b'v', ..]
+        | [_, _, _, _, b'w', b'i', b'd', b'e', ..]
+        | [_, _, _, _, b'm', b'd', b'a', b't', ..]
+        | [_, _, _, _, b'f', b'r', b'e', b'e', ..]
+        | [_, _, _, _, b's', b'k', b'i', b'p', ..] => true,
+        // Ogg (Vorbis, Opus)
+        [b'O', b'g', b'g', b'S', ..] => true,
+        [b'f', b'L', b'a', b'C', ..] => true,
+        // WAV
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => true,
+        // Matroska / WebM
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => true,
+        _ => false,
+    }
+}
+
 /// Generate a filename from an episode title
 pub fn generate_filename(title: &str, extension: &str) -> String {
     // Remove invalid characters and limit length
-    let sanitized = title
-        .chars()
-        .filter(|c| c.is_alphanumeric() || *c == ' ' || *c == '-' || *c == '_')
-        .collect::<String>();
-
-    let trimmed = sanitized.trim();
-    let limited = if trimmed.len() > 100 {
-        &trimmed[..100]
-    } else {
-        trimmed
-    };
+    let sanitized = sanitize_name(title);
+    let mut len = sanitized.len().min(100);
+    // Titles can have multi-byte letters, which mustn't be split
+    while !sanitized.is_char_boundary(len) {
+        len -= 1;
+    }
+    let limited = &sanitized[..len];
 
     format!("{}.{}", limited.trim(), extension)
 }
+
+/// Whether a path has one of the recognized episode media extensions
+pub fn is_media_file(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| MEDIA_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// File extension for a media MIME type
+pub fn extension_for_mime(mime_type: &str) -> Option<&'static str> {
+    match mime_type {
+        "audio/mpeg" | "audio/mp3" => Some("mp3"),
+        "audio/mp4" | "audio/x-m4a" | "audio/m4a" => Some("m4a"),
+        "audio/aac" | "audio/x-aac" => Some("aac"),
+        "audio/ogg" | "audio/vorbis" => Some("ogg"),
+        "audio/opus" => Some("opus"),
+        "audio/flac" | "audio/x-flac" => Some("flac"),
+        "audio/wav" | "audio/x-wav" => Some("wav"),
+        "video/mp4" => Some("mp4"),
+        "video/x-m4v" => Some("m4v"),
+        "video/quicktime" => Some("mov"),
+        _ => None,
+    }
+}
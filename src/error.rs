@@ -0,0 +1,203 @@
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::http::HttpError;
+
+/// Errors from fetching, parsing, saving and tagging episodes, grouped into
+/// kinds so callers can react to them and runs can report them by kind
+#[derive(Debug, thiserror::Error)]
+pub enum PgError {
+    #[error(transparent)]
+    Http(#[from] HttpError),
+    #[error("Failed to parse {what}: {message}")]
+    Parse { what: &'static str, message: String },
+    #[error("Failed to {action} {}: {source}", path.display())]
+    Filesystem {
+        action: &'static str,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{0}")]
+    Config(String),
+    #[error("Downloaded file is not media: {0}")]
+    InvalidMedia(String),
+    #[error("Failed to tag {}: {message}", path.display())]
+    Tagging { path: PathBuf, message: String },
+    #[error("Failed to process {}: {message}", path.display())]
+    Processing { path: PathBuf, message: String },
+    #[error("Another pg instance is already running (lock held on {})", .0.display())]
+    Locked(PathBuf),
+}
+
+/// The broad category of a [`PgError`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Network,
+    HttpStatus,
+    Parse,
+    Filesystem,
+    Config,
+    InvalidMedia,
+    Tagging,
+    Processing,
+    Locked,
+    Other,
+}
+
+impl PgError {
+    pub fn parse(what: &'static str, error: impl fmt::Display) -> Self {
+        PgError::Parse {
+            what,
+            message: error.to_string(),
+        }
+    }
+
+    pub fn filesystem(action: &'static str, path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        PgError::Filesystem {
+            action,
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// A config problem, keeping any context chain in the message
+    pub fn config(error: impl fmt::Display) -> Self {
+        PgError::Config(format!("{:#}", error))
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            PgError::Http(HttpError::Status { .. }) => ErrorKind::HttpStatus,
+            PgError::Http(_) => ErrorKind::Network,
+            PgError::Parse { .. } => ErrorKind::Parse,
+            PgError::Filesystem { .. } => ErrorKind::Filesystem,
+            PgError::Config(_) => ErrorKind::Config,
+            PgError::InvalidMedia(_) => ErrorKind::InvalidMedia,
+            PgError::Tagging { .. } => ErrorKind::Tagging,
+            PgError::Processing { .. } => ErrorKind::Processing,
+            PgError::Locked(_) => ErrorKind::Locked,
+        }
+    }
+}
+
+impl ErrorKind {
+    /// Classify an error by the first [`PgError`] or [`HttpError`] in its chain
+    pub fn of(error: &anyhow::Error) -> Self {
+        error
+            .chain()
+            .find_map(|cause| {
+                cause
+                    .downcast_ref::<PgError>()
+                    .map(PgError::kind)
+                    .or_else(|| {
+                        cause.downcast_ref::<HttpError>().map(|e| match e {
+                            HttpError::Status { .. } => ErrorKind::HttpStatus,
+                            _ => ErrorKind::Network,
+                        })
+                    })
+            })
+            .unwrap_or(ErrorKind::Other)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            ErrorKind::Network => "network",
+            ErrorKind::HttpStatus => "HTTP status",
+            ErrorKind::Parse => "parse",
+            ErrorKind::Filesystem => "filesystem",
+            ErrorKind::Config => "config",
+            ErrorKind::InvalidMedia => "invalid media",
+            ErrorKind::Tagging => "tagging",
+            ErrorKind::Processing => "processing",
+            ErrorKind::Locked => "locked",
+            ErrorKind::Other => "other",
+        };
+        f.write_str(label)
+    }
+}
+
+/// A failure collected during a run, for the end-of-run summary
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    pub podcast: String,
+    /// The episode that failed, or `None` if the whole podcast did
+    pub episode: Option<String>,
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(podcast: &str, episode: Option<&str>, error: &anyhow::Error) -> Self {
+        Failure {
+            podcast: podcast.to_string(),
+            episode: episode.map(str::to_string),
+            kind: ErrorKind::of(error),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.episode {
+            Some(episode) => write!(f, "{} / '{}': {}", self.podcast, episode, self.message),
+            None => write!(f, "{}: {}", self.podcast, self.message),
+        }
+    }
+}
+
+/// Process exit codes, as documented in the README
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Success = 0,
+    /// Any error not covered below
+    Error = 1,
+    // 2 is left to clap for usage errors
+    /// Some feeds or episodes failed, others succeeded
+    PartialFailure = 3,
+    /// Every feed that was checked failed
+    TotalFailure = 4,
+    /// The config file is missing or invalid
+    Config = 5,
+    /// Another instance holds the state lock
+    Locked = 6,
+}
+
+impl ExitStatus {
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// Exit status for a command that returned an error. A fetch or
+    /// download error means the command got nothing done.
+    pub fn for_error(error: &anyhow::Error) -> Self {
+        match ErrorKind::of(error) {
+            ErrorKind::Config => ExitStatus::Config,
+            ErrorKind::Locked => ExitStatus::Locked,
+            ErrorKind::Other => ExitStatus::Error,
+            _ => ExitStatus::TotalFailure,
+        }
+    }
+
+    /// Exit status for a run with the given failures, where `succeeded`
+    /// podcasts were processed with at least something to show for it
+    pub fn for_run(failures: &[Failure], succeeded: usize) -> Self {
+        if failures.is_empty() {
+            ExitStatus::Success
+        } else if succeeded == 0 {
+            ExitStatus::TotalFailure
+        } else {
+            ExitStatus::PartialFailure
+        }
+    }
+}
+
+impl From<ExitStatus> for std::process::ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        std::process::ExitCode::from(status.code())
+    }
+}
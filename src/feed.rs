@@ -1,72 +1,565 @@
-use anyhow::{Context, Result};
-use chrono::{DateTime, Utc};
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
 use rss::Channel;
-use std::str::FromStr;
+use std::cmp::Reverse;
+use std::path::Path;
+
+use crate::config::{Backfill, EnclosurePreference, EpisodeOrder, MediaKind, Quality};
+use crate::error::PgError;
+use crate::http::HttpClient;
 
 #[derive(Debug, Clone)]
 pub struct Episode {
+    pub guid: Option<String>,
     pub title: String,
-    #[allow(dead_code)]
     pub description: Option<String>,
     pub url: String,
     pub pub_date: DateTime<Utc>,
+    /// False when the feed gave no parseable date and `pub_date` is a placeholder
+    pub pub_date_known: bool,
+    /// Running time in seconds, from `itunes:duration`
+    pub duration: Option<u64>,
+    /// Enclosure size in bytes, as advertised by the feed
+    pub size: Option<u64>,
+    /// Season number, from `itunes:season`
+    pub season: Option<u32>,
+    /// Episode number within the season, from `itunes:episode`
+    pub episode_number: Option<u32>,
+    /// `itunes:episodeType`: "full", "trailer" or "bonus"
+    pub episode_type: Option<String>,
+    /// MIME type of the enclosure, as advertised by the feed
+    pub mime_type: Option<String>,
+    /// Every media file the item offers, primary enclosure first
+    pub enclosures: Vec<MediaEnclosure>,
+    /// JSON chapters document, from `podcast:chapters`
+    pub chapters_url: Option<String>,
+    /// Transcripts offered with `podcast:transcript`, in feed order
+    pub transcripts: Vec<TranscriptLink>,
+    /// The episode's own artwork, from `itunes:image`
+    pub image_url: Option<String>,
+}
+
+/// A transcript linked from a feed item
+#[derive(Debug, Clone)]
+pub struct TranscriptLink {
+    pub url: String,
+    pub mime_type: Option<String>,
+}
+
+/// Podcast-level details from a feed's channel
+#[derive(Debug, Clone, Default)]
+pub struct FeedInfo {
+    pub title: String,
+    /// The podcast's website
+    pub link: Option<String>,
+    /// `itunes:author`
+    pub author: Option<String>,
+    /// `itunes:category` names, then plain RSS categories
+    pub categories: Vec<String>,
+    pub image_url: Option<String>,
+}
+
+/// One media file offered by a feed item, from `enclosure`,
+/// `podcast:alternateEnclosure` or `media:content`
+#[derive(Debug, Clone)]
+pub struct MediaEnclosure {
+    pub url: String,
+    pub mime_type: Option<String>,
+    /// Size in bytes
+    pub length: Option<u64>,
+    /// Bits per second
+    pub bitrate: Option<u64>,
+    /// Video height in pixels
+    pub height: Option<u64>,
+}
+
+impl MediaEnclosure {
+    fn is_video(&self) -> bool {
+        self.mime_type.as_deref().is_some_and(|t| t.starts_with("video/"))
+    }
+}
+
+impl Episode {
+    /// Whether the chosen media file is a video, going by its MIME type
+    pub fn is_video(&self) -> bool {
+        self.mime_type.as_deref().is_some_and(|t| t.starts_with("video/"))
+    }
+
+    /// Stable identifier for the episode: its GUID, or the primary media URL
+    /// if the feed has none
+    pub fn id(&self) -> &str {
+        self.guid
+            .as_deref()
+            .or_else(|| self.enclosures.first().map(|e| e.url.as_str()))
+            .unwrap_or(&self.url)
+    }
+
+    /// Switch the episode to the given media file
+    fn use_enclosure(&mut self, enclosure: MediaEnclosure) {
+        self.url = enclosure.url;
+        self.mime_type = enclosure.mime_type;
+        self.size = enclosure.length;
+    }
+}
+
+/// Fetch and parse RSS feed, keeping a copy of the raw feed in `cache_file`
+pub async fn fetch_feed(
+    http: &HttpClient,
+    feed_url: &str,
+    cache_file: Option<&Path>,
+) -> Result<(Vec<Episode>, FeedInfo), PgError> {
+    let body = http.get_bytes(feed_url).await?;
+
+    let parsed = parse_feed(&body)?;
+
+    // Cached as served, so the XML declaration's encoding still applies
+    if let Some(cache_file) = cache_file {
+        if let Err(e) = write_cache(cache_file, &body) {
+            tracing::debug!("Failed to cache feed at {}: {}", cache_file.display(), e);
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Parse a previously cached copy of a feed
+pub fn load_cached_feed(cache_file: &Path) -> Result<(Vec<Episode>, FeedInfo), PgError> {
+    let content = std::fs::read(cache_file)
+        .map_err(|e| PgError::filesystem("read cached feed", cache_file, e))?;
+    parse_feed(&content)
 }
 
-/// Fetch and parse RSS feed
-pub async fn fetch_feed(feed_url: &str) -> Result<(Vec<Episode>, Option<String>)> {
-    let content = reqwest::get(feed_url)
-        .await
-        .context("Failed to fetch feed")?
-        .text()
-        .await
-        .context("Failed to read feed content")?;
+fn write_cache(cache_file: &Path, content: &[u8]) -> Result<()> {
+    if let Some(parent) = cache_file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(cache_file, content)?;
+    Ok(())
+}
 
-    let channel = Channel::from_str(&content)
-        .context("Failed to parse RSS feed")?;
+/// Parse RSS feed content into episodes and the podcast's details. The
+/// bytes are decoded as the feed's XML declaration says, UTF-8 by default.
+pub fn parse_feed(content: &[u8]) -> Result<(Vec<Episode>, FeedInfo), PgError> {
+    let channel = Channel::read_from(content)
+        .map_err(|e| PgError::parse("RSS feed", e))?;
 
     let mut episodes = Vec::new();
 
     for item in channel.items() {
-        // Try to extract URL from various possible locations
-        let url = item
-            .enclosure()
-            .map(|e| e.url().to_string())
+        let title = item
+            .title()
+            .map(|t| t.to_string())
+            .unwrap_or_else(|| "Unknown".to_string());
+
+        let enclosures = extract_enclosures(item);
+        let Some(primary) = enclosures.first().cloned() else {
+            tracing::warn!("Skipping '{}': item has no media enclosure", title);
+            continue;
+        };
+
+        let description = item.description().map(|d| d.to_string());
+
+        let guid = item.guid().map(|g| g.value().to_string());
+
+        // Fall back to dc:date when pubDate is missing or unparseable
+        let pub_date = item
+            .pub_date()
+            .and_then(parse_date)
             .or_else(|| {
-                item.link()
-                    .map(|l| l.to_string())
+                item.dublin_core_ext()
+                    .and_then(|dc| dc.dates().iter().find_map(|d| parse_date(d)))
             });
+        let pub_date_known = pub_date.is_some();
+        let pub_date = pub_date.unwrap_or_else(Utc::now);
 
-        if let Some(url) = url {
-            let title = item
-                .title()
-                .map(|t| t.to_string())
-                .unwrap_or_else(|| "Unknown".to_string());
-
-            let description = item.description().map(|d| d.to_string());
-
-            let pub_date = item
-                .pub_date()
-                .and_then(|date_str| {
-                    // Try to parse RFC 2822 format (common in RSS)
-                    DateTime::parse_from_rfc2822(date_str)
-                        .ok()
-                        .map(|dt| dt.with_timezone(&Utc))
+        let duration = item
+            .itunes_ext()
+            .and_then(|ext| ext.duration())
+            .and_then(parse_duration);
+
+        let season = item
+            .itunes_ext()
+            .and_then(|ext| ext.season())
+            .and_then(|s| s.trim().parse().ok());
+
+        let episode_number = item
+            .itunes_ext()
+            .and_then(|ext| ext.episode())
+            .and_then(|e| e.trim().parse().ok());
+
+        let episode_type = item
+            .itunes_ext()
+            .and_then(|ext| ext.episode_type())
+            .map(|t| t.trim().to_lowercase());
+
+        let chapters_url = item
+            .extensions()
+            .get("podcast")
+            .and_then(|ext| ext.get("chapters"))
+            .and_then(|chapters| chapters.first())
+            .and_then(|chapters| chapters.attrs.get("url"))
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty());
+
+        let image_url = item
+            .itunes_ext()
+            .and_then(|ext| ext.image())
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty());
+
+        let transcripts = item
+            .extensions()
+            .get("podcast")
+            .and_then(|ext| ext.get("transcript"))
+            .into_iter()
+            .flatten()
+            .filter_map(|transcript| {
+                let url = transcript.attrs.get("url")?.trim();
+                (!url.is_empty()).then(|| TranscriptLink {
+                    url: url.to_string(),
+                    mime_type: transcript.attrs.get("type").map(|t| t.trim().to_lowercase()),
                 })
-                .unwrap_or_else(|| Utc::now());
+            })
+            .collect();
+
+        episodes.push(Episode {
+            guid,
+            title,
+            description,
+            url: primary.url,
+            pub_date,
+            pub_date_known,
+            duration,
+            size: primary.length,
+            season,
+            episode_number,
+            episode_type,
+            mime_type: primary.mime_type,
+            enclosures,
+            chapters_url,
+            transcripts,
+            image_url,
+        });
+    }
 
-            episodes.push(Episode {
-                title,
-                description,
-                url,
-                pub_date,
+    let info = FeedInfo {
+        title: channel.title().trim().to_string(),
+        link: Some(channel.link().trim())
+            .filter(|link| !link.is_empty())
+            .map(str::to_string),
+        author: channel
+            .itunes_ext()
+            .and_then(|ext| ext.author())
+            .map(|author| author.trim().to_string()),
+        categories: extract_categories(&channel),
+        image_url: extract_channel_image(&channel),
+    };
+
+    Ok((episodes, info))
+}
+
+/// Category names, iTunes categories first, without duplicates
+fn extract_categories(channel: &Channel) -> Vec<String> {
+    let itunes = channel
+        .itunes_ext()
+        .map(|ext| ext.categories())
+        .unwrap_or_default()
+        .iter()
+        .flat_map(|category| {
+            std::iter::once(category.text())
+                .chain(category.subcategory().map(|sub| sub.text()))
+        });
+    let plain = channel.categories().iter().map(|category| category.name());
+
+    let mut categories: Vec<String> = Vec::new();
+    for name in itunes.chain(plain).map(str::trim) {
+        if !name.is_empty() && !categories.iter().any(|c| c == name) {
+            categories.push(name.to_string());
+        }
+    }
+    categories
+}
+
+/// Collect every media file an item offers: the RSS enclosure, then
+/// `podcast:alternateEnclosure` and `media:content` alternatives. The item's
+/// link is only used when it points at a media file rather than a web page.
+fn extract_enclosures(item: &rss::Item) -> Vec<MediaEnclosure> {
+    let mut enclosures = Vec::new();
+
+    if let Some(e) = item.enclosure() {
+        enclosures.push(MediaEnclosure {
+            url: e.url().to_string(),
+            mime_type: Some(e.mime_type().trim().to_lowercase()).filter(|t| !t.is_empty()),
+            length: parse_number(e.length()),
+            bitrate: None,
+            height: None,
+        });
+    }
+
+    let extensions = item.extensions();
+
+    let alternates = extensions
+        .get("podcast")
+        .and_then(|ext| ext.get("alternateEnclosure"))
+        .into_iter()
+        .flatten();
+    for alt in alternates {
+        // Prefer plain HTTP sources over torrents and IPFS
+        let url = alt
+            .children
+            .get("source")
+            .into_iter()
+            .flatten()
+            .filter_map(|source| source.attrs.get("uri"))
+            .find(|uri| uri.starts_with("http://") || uri.starts_with("https://"));
+        if let Some(url) = url {
+            enclosures.push(MediaEnclosure {
+                url: url.clone(),
+                mime_type: alt.attrs.get("type").map(|t| t.trim().to_lowercase()),
+                length: alt.attrs.get("length").and_then(|l| parse_number(l)),
+                bitrate: alt.attrs.get("bitrate").and_then(|b| parse_number(b)),
+                height: alt.attrs.get("height").and_then(|h| parse_number(h)),
             });
         }
     }
 
-    // Extract channel image URL
-    let image_url = extract_channel_image(&channel);
+    if let Some(media) = extensions.get("media") {
+        let grouped = media
+            .get("group")
+            .into_iter()
+            .flatten()
+            .flat_map(|group| group.children.get("content").into_iter().flatten());
+        let contents = media.get("content").into_iter().flatten().chain(grouped);
+        for content in contents {
+            if let Some(url) = content.attrs.get("url") {
+                let mime_type = content
+                    .attrs
+                    .get("type")
+                    .map(|t| t.trim().to_lowercase())
+                    .or_else(|| match content.attrs.get("medium").map(String::as_str) {
+                        Some("audio") => Some("audio/*".to_string()),
+                        Some("video") => Some("video/*".to_string()),
+                        _ => None,
+                    });
+                // media:content gives bitrate in kilobits per second
+                enclosures.push(MediaEnclosure {
+                    url: url.clone(),
+                    mime_type,
+                    length: content.attrs.get("fileSize").and_then(|l| parse_number(l)),
+                    bitrate: content
+                        .attrs
+                        .get("bitrate")
+                        .and_then(|b| parse_number(b))
+                        .map(|kbps| kbps * 1000),
+                    height: content.attrs.get("height").and_then(|h| parse_number(h)),
+                });
+            }
+        }
+    }
+
+    if enclosures.is_empty() {
+        if let Some(link) = item.link() {
+            if crate::download::is_media_file(Path::new(strip_query(link))) {
+                enclosures.push(MediaEnclosure {
+                    url: link.to_string(),
+                    mime_type: None,
+                    length: None,
+                    bitrate: None,
+                    height: None,
+                });
+            }
+        }
+    }
+
+    // Drop what is known not to be media, such as web pages and images.
+    // Generic types like `application/octet-stream` and `application/ogg`
+    // are common for real episodes, so they are kept.
+    enclosures.retain(|e| e.mime_type.as_deref().is_none_or(|t| !is_non_media_type(t)));
+    let mut seen = std::collections::HashSet::new();
+    enclosures.retain(|e| seen.insert(e.url.clone()));
+
+    enclosures
+}
 
-    Ok((episodes, image_url))
+/// Whether a MIME type is certainly not an episode's media
+fn is_non_media_type(mime_type: &str) -> bool {
+    let mime_type = mime_type.to_ascii_lowercase();
+    mime_type.starts_with("text/") || mime_type.starts_with("image/") || mime_type == "application/pdf"
+}
+
+/// Parse a positive number attribute, tolerating decimals
+fn parse_number(value: &str) -> Option<u64> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|n| *n > 0.0)
+        .map(|n| n as u64)
+}
+
+fn strip_query(url: &str) -> &str {
+    url.split(['?', '#']).next().unwrap_or(url)
+}
+
+/// Pick each episode's media file according to the podcast's preferences.
+///
+/// Candidates are ranked by audio/video preference, then by position in the
+/// `prefer` MIME type list, then by quality. Without any preference the
+/// feed's primary enclosure is kept.
+pub fn choose_enclosures(episodes: &mut [Episode], prefs: &EnclosurePreference) {
+    if prefs.prefer.is_empty() && prefs.prefer_quality.is_none() && prefs.prefer_media.is_none() {
+        return;
+    }
+
+    let prefer: Vec<String> = prefs.prefer.iter().map(|t| t.to_lowercase()).collect();
+
+    for episode in episodes.iter_mut() {
+        let mut candidates = episode.enclosures.clone();
+        // Stable sort, so ties keep the feed's own order
+        candidates.sort_by_key(|e| {
+            let media_rank = match prefs.prefer_media {
+                Some(MediaKind::Video) => !e.is_video(),
+                Some(MediaKind::Audio) => e.is_video(),
+                None => false,
+            };
+            let type_rank = e
+                .mime_type
+                .as_deref()
+                .and_then(|t| prefer.iter().position(|p| mime_matches(p, t)))
+                .unwrap_or(prefer.len());
+            let quality = (e.height.unwrap_or(0), e.bitrate.unwrap_or(0), e.length.unwrap_or(0));
+            // Highest quality first, or lowest first; the other half of the
+            // key stays level
+            let quality_rank = match prefs.prefer_quality {
+                Some(Quality::High) => (Reverse(quality), (0, 0, 0)),
+                Some(Quality::Low) => (Reverse((0, 0, 0)), quality),
+                None => (Reverse((0, 0, 0)), (0, 0, 0)),
+            };
+            (media_rank, type_rank, quality_rank)
+        });
+
+        if let Some(best) = candidates.into_iter().next() {
+            if best.url != episode.url {
+                tracing::debug!("Using {} for '{}'", best.url, episode.title);
+                episode.use_enclosure(best);
+            }
+        }
+    }
+}
+
+/// Match a MIME type against a pattern, where `audio/*` matches any audio type
+pub fn mime_matches(pattern: &str, mime_type: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(prefix) => mime_type.split('/').next() == Some(prefix),
+        None => pattern == mime_type,
+    }
+}
+
+/// Named time zones seen in the wild, with their UTC offsets
+const ZONE_OFFSETS: &[(&str, &str)] = &[
+    ("UT", "+0000"),
+    ("UTC", "+0000"),
+    ("GMT", "+0000"),
+    ("Z", "+0000"),
+    ("EST", "-0500"),
+    ("EDT", "-0400"),
+    ("CST", "-0600"),
+    ("CDT", "-0500"),
+    ("MST", "-0700"),
+    ("MDT", "-0600"),
+    ("PST", "-0800"),
+    ("PDT", "-0700"),
+    ("AKST", "-0900"),
+    ("AKDT", "-0800"),
+    ("HST", "-1000"),
+    ("BST", "+0100"),
+    ("IST", "+0530"),
+    ("CET", "+0100"),
+    ("CEST", "+0200"),
+    ("EET", "+0200"),
+    ("EEST", "+0300"),
+    ("JST", "+0900"),
+    ("AEST", "+1000"),
+    ("AEDT", "+1100"),
+];
+
+/// Date layouts tried after normalizing the weekday and time zone away
+const DATE_FORMATS: &[&str] = &[
+    "%d %b %Y %H:%M:%S %z",
+    "%d %b %Y %H:%M %z",
+    "%d %B %Y %H:%M:%S %z",
+    "%d %B %Y %H:%M %z",
+    "%d %b %y %H:%M:%S %z",
+    "%Y-%m-%d %H:%M:%S %z",
+];
+
+/// Parse a feed date as leniently as possible.
+///
+/// Accepts RFC 2822 (with or without the weekday, and ignoring a weekday that
+/// doesn't match the date), named time zones such as `PDT`, and ISO 8601 /
+/// RFC 3339 timestamps or bare dates. Dates without a zone are taken as UTC.
+pub fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+
+    if let Ok(dt) = DateTime::parse_from_rfc2822(value) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    if let Ok(dt) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f") {
+        return Some(dt.and_utc());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return date.and_hms_opt(0, 0, 0).map(|dt| dt.and_utc());
+    }
+
+    // Drop a leading weekday ("Mon," or "Monday"), which may be wrong or missing
+    let mut parts: Vec<&str> = value.split_whitespace().collect();
+    if parts
+        .first()
+        .is_some_and(|p| p.trim_end_matches(',').chars().all(|c| c.is_alphabetic()))
+    {
+        parts.remove(0);
+    }
+
+    // Replace a named zone with its offset, or assume UTC when there is none
+    let mut normalized: Vec<String> = parts.iter().map(|p| p.replace(',', "")).collect();
+    match normalized.last().map(String::as_str) {
+        Some(zone) if zone.starts_with('+') || zone.starts_with('-') => {}
+        Some(zone) => {
+            let upper = zone.to_uppercase();
+            match ZONE_OFFSETS.iter().find(|(name, _)| *name == upper) {
+                Some((_, offset)) => {
+                    normalized.pop();
+                    normalized.push(offset.to_string());
+                }
+                None if zone.contains(':') => normalized.push("+0000".to_string()),
+                None => return None,
+            }
+        }
+        None => return None,
+    }
+    let normalized = normalized.join(" ");
+
+    DATE_FORMATS.iter().find_map(|format| {
+        DateTime::parse_from_str(&normalized, format)
+            .ok()
+            .map(|dt| dt.with_timezone(&Utc))
+    })
+}
+
+/// Parse an `itunes:duration` value (`HH:MM:SS`, `MM:SS` or plain seconds) into seconds
+pub fn parse_duration(value: &str) -> Option<u64> {
+    value
+        .trim()
+        .split(':')
+        .try_fold(0u64, |total, part| {
+            let part = part.trim();
+            // Some feeds write fractional seconds
+            let whole = part.split('.').next()?;
+            Some(total * 60 + whole.parse::<u64>().ok()?)
+        })
 }
 
 /// Extract channel image URL from RSS feed
@@ -90,3 +583,85 @@ pub fn filter_by_date(
             .collect(),
     }
 }
+
+/// Sort episodes by publication date, using season and episode number to
+/// break ties between episodes published at the same time
+pub fn sort_episodes(episodes: &mut [Episode], order: EpisodeOrder) {
+    episodes.sort_by_key(|ep| (ep.pub_date, ep.season, ep.episode_number));
+    if order == EpisodeOrder::Newest {
+        episodes.reverse();
+    }
+}
+
+/// Select the episodes a backfill should download
+pub fn filter_backfill(episodes: Vec<Episode>, backfill: Backfill) -> Vec<Episode> {
+    match backfill {
+        Backfill::None => Vec::new(),
+        Backfill::Latest(count) => {
+            let mut episodes = episodes;
+            sort_episodes(&mut episodes, EpisodeOrder::Newest);
+            episodes.truncate(count);
+            episodes
+        }
+        Backfill::Since(date) => episodes
+            .into_iter()
+            .filter(|ep| ep.pub_date.date_naive() >= date)
+            .collect(),
+        Backfill::All => episodes,
+    }
+}
+
+/// Find the single episode matching a selector.
+///
+/// The selector is tried, in order, as a GUID (or media URL), a 1-based index
+/// into the feed as shown by `pg episodes`, a publication date (`YYYY-MM-DD`),
+/// and finally a case-insensitive title substring.
+pub fn find_episode<'a>(episodes: &'a [Episode], selector: &str) -> Result<&'a Episode> {
+    if let Some(episode) = episodes.iter().find(|ep| ep.id() == selector || ep.url == selector) {
+        return Ok(episode);
+    }
+
+    if let Ok(index) = selector.parse::<usize>() {
+        return index
+            .checked_sub(1)
+            .and_then(|i| episodes.get(i))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "No episode at index {} (feed has {} episodes)",
+                    index,
+                    episodes.len()
+                )
+            });
+    }
+
+    let matches: Vec<&Episode> = match NaiveDate::parse_from_str(selector, "%Y-%m-%d") {
+        Ok(date) => episodes
+            .iter()
+            .filter(|ep| ep.pub_date.date_naive() == date)
+            .collect(),
+        Err(_) => {
+            let needle = selector.to_lowercase();
+            episodes
+                .iter()
+                .filter(|ep| ep.title.to_lowercase().contains(&needle))
+                .collect()
+        }
+    };
+
+    match matches.as_slice() {
+        [] => anyhow::bail!("No episode matches '{}'", selector),
+        [episode] => Ok(episode),
+        many => {
+            let titles: Vec<String> = many
+                .iter()
+                .map(|ep| format!("{} {}", ep.pub_date.format("%Y-%m-%d"), ep.title))
+                .collect();
+            anyhow::bail!(
+                "'{}' matches {} episodes, be more specific or use an index from `pg episodes`:\n  {}",
+                selector,
+                many.len(),
+                titles.join("\n  ")
+            )
+        }
+    }
+}
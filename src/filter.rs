@@ -0,0 +1,96 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use crate::config::FilterConfig;
+use crate::feed::{mime_matches, Episode};
+
+/// A podcast's episode filters, compiled and ready to apply
+pub struct EpisodeFilter {
+    include_title: Option<Regex>,
+    exclude_title: Option<Regex>,
+    exclude_episode_types: Vec<String>,
+    min_duration: Option<u64>,
+    max_duration: Option<u64>,
+    mime_types: Vec<String>,
+}
+
+impl EpisodeFilter {
+    /// Compile the filters from a podcast's config
+    pub fn new(config: &FilterConfig) -> Result<Self> {
+        let compile = |pattern: &Option<String>, setting: &str| {
+            pattern
+                .as_deref()
+                .map(|p| Regex::new(p).with_context(|| format!("Invalid `{}` regex '{}'", setting, p)))
+                .transpose()
+        };
+
+        Ok(EpisodeFilter {
+            include_title: compile(&config.include_title, "include_title")?,
+            exclude_title: compile(&config.exclude_title, "exclude_title")?,
+            exclude_episode_types: config
+                .exclude_episode_types
+                .iter()
+                .map(|t| t.to_lowercase())
+                .collect(),
+            min_duration: config.min_duration.map(|d| d.0),
+            max_duration: config.max_duration.map(|d| d.0),
+            mime_types: config.mime_types.iter().map(|t| t.to_lowercase()).collect(),
+        })
+    }
+
+    /// Why an episode should be skipped, or `None` if it passes every filter.
+    ///
+    /// Episodes missing the information a filter needs (no duration, no
+    /// episode type, no MIME type) are kept.
+    pub fn skip_reason(&self, episode: &Episode) -> Option<String> {
+        if let Some(re) = &self.include_title {
+            if !re.is_match(&episode.title) {
+                return Some(format!("title does not match include_title /{}/", re));
+            }
+        }
+
+        if let Some(re) = &self.exclude_title {
+            if re.is_match(&episode.title) {
+                return Some(format!("title matches exclude_title /{}/", re));
+            }
+        }
+
+        if let Some(episode_type) = &episode.episode_type {
+            if self.exclude_episode_types.contains(episode_type) {
+                return Some(format!("episode type '{}' is excluded", episode_type));
+            }
+        }
+
+        if let Some(duration) = episode.duration {
+            if let Some(min) = self.min_duration.filter(|&min| duration < min) {
+                return Some(format!("shorter than min_duration ({}s < {}s)", duration, min));
+            }
+            if let Some(max) = self.max_duration.filter(|&max| duration > max) {
+                return Some(format!("longer than max_duration ({}s > {}s)", duration, max));
+            }
+        }
+
+        if let Some(mime_type) = &episode.mime_type {
+            if !self.mime_types.is_empty()
+                && !self.mime_types.iter().any(|allowed| mime_matches(allowed, mime_type))
+            {
+                return Some(format!("MIME type '{}' is not in mime_types", mime_type));
+            }
+        }
+
+        None
+    }
+
+    /// Split episodes into those to keep and those skipped by the filters
+    pub fn apply(&self, episodes: Vec<Episode>) -> (Vec<Episode>, Vec<(Episode, String)>) {
+        let mut kept = Vec::new();
+        let mut skipped = Vec::new();
+        for episode in episodes {
+            match self.skip_reason(&episode) {
+                Some(reason) => skipped.push((episode, reason)),
+                None => kept.push(episode),
+            }
+        }
+        (kept, skipped)
+    }
+}
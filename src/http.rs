@@ -0,0 +1,385 @@
+use anyhow::{Context, Result};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, COOKIE, ETAG, IF_NONE_MATCH, RETRY_AFTER,
+};
+use reqwest::StatusCode;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::config::{AuthConfig, HttpConfig, PodcastConfig};
+
+/// User-Agent sent when the config doesn't set one
+pub const DEFAULT_USER_AGENT: &str = concat!("podcast-getter/", env!("CARGO_PKG_VERSION"));
+
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_READ_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_RETRIES: u32 = 3;
+
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+const RETRY_AFTER_MAX: Duration = Duration::from_secs(300);
+
+/// The shared HTTP client used for feeds, episodes and cover art.
+///
+/// Cloning is cheap: clones share the underlying connection pool, so
+/// per-podcast variants made with [`HttpClient::for_podcast`] reuse it too.
+#[derive(Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    /// Extra headers sent with every request, on top of the client defaults
+    headers: HeaderMap,
+    /// Longest time to wait for the response or the next chunk of the body
+    read_timeout: Duration,
+    /// How many times to retry a transient failure
+    retries: u32,
+    /// Resolved credentials for a private feed
+    auth: Option<Auth>,
+    /// Lowercased hosts the credentials are sent to
+    auth_hosts: Vec<String>,
+}
+
+/// Credentials with their secrets resolved
+#[derive(Clone)]
+enum Auth {
+    Basic { username: String, password: String },
+    Bearer(String),
+    Cookie(HeaderValue),
+}
+
+impl HttpClient {
+    /// Build the client from the `[http]` config section
+    pub fn new(config: &HttpConfig) -> Result<Self> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(config.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT))
+            .connect_timeout(Duration::from_secs(
+                config.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS),
+            ))
+            .default_headers(header_map(&config.headers)?);
+
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(
+                reqwest::Proxy::all(proxy).with_context(|| format!("Invalid proxy '{}'", proxy))?,
+            );
+        }
+
+        Ok(HttpClient {
+            client: builder.build().context("Failed to build HTTP client")?,
+            headers: HeaderMap::new(),
+            retries: config.retries.unwrap_or(DEFAULT_RETRIES),
+            auth: None,
+            auth_hosts: Vec::new(),
+            read_timeout: Duration::from_secs(
+                config.read_timeout.unwrap_or(DEFAULT_READ_TIMEOUT_SECS),
+            ),
+        })
+    }
+
+    /// A client that also sends the podcast's own header overrides and credentials
+    pub fn for_podcast(&self, podcast: &PodcastConfig) -> Result<Self> {
+        let mut client = self.clone();
+        let overrides = header_map(&podcast.headers)
+            .with_context(|| format!("Invalid headers for podcast '{}'", podcast.name))?;
+        for (name, value) in overrides.iter() {
+            client.headers.insert(name.clone(), value.clone());
+        }
+
+        if let Some(auth) = &podcast.auth {
+            let resolved = match auth {
+                AuthConfig::Basic { username, password } => Auth::Basic {
+                    username: username.clone(),
+                    password: password.resolve()?,
+                },
+                AuthConfig::Bearer { token } => Auth::Bearer(token.resolve()?),
+                AuthConfig::Cookie { cookie } => {
+                    let mut value = HeaderValue::from_str(&cookie.resolve()?)
+                        .context("Invalid cookie value")?;
+                    value.set_sensitive(true);
+                    Auth::Cookie(value)
+                }
+            };
+            client.auth = Some(resolved);
+
+            // Only the feed's own host, and any the config names, get the
+            // credentials, not every host its items link to
+            let feed_host = host_of(&podcast.url)
+                .with_context(|| format!("Feed URL '{}' has no host", podcast.url))?;
+            client.auth_hosts = std::iter::once(feed_host)
+                .chain(podcast.auth_hosts.iter().map(|host| host.to_lowercase()))
+                .collect();
+        }
+
+        Ok(client)
+    }
+
+    /// GET a URL and return its body, retrying transient failures
+    pub async fn get_bytes(&self, url: &str) -> Result<Vec<u8>, HttpError> {
+        Ok(self.fetch(url).await?.body)
+    }
+
+    /// GET a URL, retrying timeouts, connection errors, 429 and 5xx responses
+    /// with exponential backoff. Other failures, such as 404, fail at once.
+    pub async fn fetch(&self, url: &str) -> Result<Fetched, HttpError> {
+        self.fetch_with_retries(url, None).await
+    }
+
+    /// Like [`HttpClient::fetch`], but sends `etag` as `If-None-Match` and
+    /// returns `None` if the server says the resource hasn't changed
+    pub async fn fetch_if_changed(&self, url: &str, etag: Option<&str>) -> Result<Option<Fetched>, HttpError> {
+        match self.fetch_with_retries(url, etag).await {
+            Ok(fetched) => Ok(Some(fetched)),
+            // Not transient, so it comes straight back without retries
+            Err(HttpError::Status { status, .. }) if status == StatusCode::NOT_MODIFIED && etag.is_some() => {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn fetch_with_retries(&self, url: &str, etag: Option<&str>) -> Result<Fetched, HttpError> {
+        let mut attempt = 0;
+        loop {
+            match self.fetch_once(url, etag).await {
+                Ok(fetched) => return Ok(fetched),
+                Err(e) if e.is_transient() && attempt < self.retries => {
+                    let delay = e.retry_after().unwrap_or_else(|| backoff(attempt));
+                    attempt += 1;
+                    tracing::warn!(
+                        "{} (retry {}/{} in {:.1}s)",
+                        e,
+                        attempt,
+                        self.retries,
+                        delay.as_secs_f64()
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn fetch_once(&self, url: &str, etag: Option<&str>) -> Result<Fetched, HttpError> {
+        let mut request = self.client.get(url).headers(self.headers.clone());
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let auth = self.auth.as_ref().filter(|_| {
+            host_of(url).is_some_and(|host| self.auth_hosts.contains(&host))
+        });
+        request = match auth {
+            Some(Auth::Basic { username, password }) => request.basic_auth(username, Some(password)),
+            Some(Auth::Bearer(token)) => request.bearer_auth(token),
+            Some(Auth::Cookie(cookie)) => request.header(COOKIE, cookie.clone()),
+            None => request,
+        };
+
+        // Wait at most the read timeout for the response headers
+        let mut response = tokio::time::timeout(self.read_timeout, request.send())
+            .await
+            .map_err(|_| HttpError::Timeout { url: url.to_string() })?
+            .map_err(|e| HttpError::from_reqwest(url, e))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(HttpError::Status {
+                url: url.to_string(),
+                status,
+                retry_after: parse_retry_after(response.headers()),
+            });
+        }
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_lowercase());
+        let content_length = response.content_length();
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+
+        // Then fail if the server stalls for longer than that between chunks
+        let mut body = Vec::with_capacity(content_length.unwrap_or(0) as usize);
+        loop {
+            let chunk = tokio::time::timeout(self.read_timeout, response.chunk())
+                .await
+                .map_err(|_| HttpError::Timeout { url: url.to_string() })?
+                .map_err(|e| HttpError::from_reqwest(url, e))?;
+            match chunk {
+                Some(chunk) => body.extend_from_slice(&chunk),
+                None => break,
+            }
+        }
+
+        Ok(Fetched {
+            body,
+            content_type,
+            content_length,
+            etag,
+        })
+    }
+}
+
+/// A successful response, read in full
+pub struct Fetched {
+    pub body: Vec<u8>,
+    pub content_type: Option<String>,
+    pub content_length: Option<u64>,
+    pub etag: Option<String>,
+}
+
+/// Why an HTTP request failed
+#[derive(Debug, thiserror::Error)]
+pub enum HttpError {
+    #[error("Timed out fetching {url}")]
+    Timeout { url: String },
+    #[error("Connection failed for {url}: {error}")]
+    Connection { url: String, error: reqwest::Error },
+    #[error("HTTP {status} from {url}")]
+    Status {
+        url: String,
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    #[error("Request failed for {url}: {error}")]
+    Request { url: String, error: reqwest::Error },
+}
+
+impl HttpError {
+    fn from_reqwest(url: &str, error: reqwest::Error) -> Self {
+        let url = url.to_string();
+        // The URL goes in our own message, once
+        let error = error.without_url();
+        if error.is_timeout() {
+            HttpError::Timeout { url }
+        } else if error.is_connect() || error.is_body() || error.is_request() {
+            HttpError::Connection { url, error }
+        } else {
+            HttpError::Request { url, error }
+        }
+    }
+
+    /// Whether trying again might succeed: timeouts, dropped connections,
+    /// rate limiting and server errors. Client errors like 404 and 410 are
+    /// permanent.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            HttpError::Timeout { .. } | HttpError::Connection { .. } => true,
+            HttpError::Status { status, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS
+                    || *status == StatusCode::REQUEST_TIMEOUT
+                    || status.is_server_error()
+            }
+            HttpError::Request { .. } => false,
+        }
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            HttpError::Status { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+/// Exponential backoff with jitter: about 1s, 2s, 4s, ... up to 30s, each
+/// scaled by a random factor between 0.5 and 1 so clients don't retry in step
+fn backoff(attempt: u32) -> Duration {
+    let base = RETRY_BASE_DELAY.saturating_mul(1 << attempt.min(5)).min(RETRY_MAX_DELAY);
+    base.mul_f64(0.5 + fastrand::f64() / 2.0)
+}
+
+/// Parse a `Retry-After` header, given either in seconds or as an HTTP date
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(secs) => Duration::from_secs(secs),
+        Err(_) => {
+            let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+            (at.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or_default()
+        }
+    };
+    // Don't let a server park us for too long
+    Some(delay.min(RETRY_AFTER_MAX))
+}
+
+/// The lowercased host of a URL, if it parses and has one
+fn host_of(url: &str) -> Option<String> {
+    reqwest::Url::parse(url).ok()?.host_str().map(str::to_lowercase)
+}
+
+/// Turn configured header names and values into a `HeaderMap`
+fn header_map(headers: &BTreeMap<String, String>) -> Result<HeaderMap> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("Invalid header name '{}'", name))?;
+        let value = HeaderValue::from_str(value)
+            .with_context(|| format!("Invalid value for header '{}'", name))?;
+        map.insert(name, value);
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secrets::SecretRef;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serve `requests` requests on a local port, sending back each request's
+    /// head as lowercase text. Returns the port.
+    async fn echo_server(requests: usize) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            for _ in 0..requests {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut head = Vec::new();
+                let mut buf = [0; 1024];
+                while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    head.extend_from_slice(&buf[..n]);
+                }
+                let head = String::from_utf8_lossy(&head).to_lowercase();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    head.len(),
+                    head
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn auth_is_only_sent_to_the_feed_host() {
+        std::env::set_var("PG_TEST_AUTH_TOKEN", "s3cret");
+        let port = echo_server(2).await;
+        let podcast = PodcastConfig {
+            url: format!("http://localhost:{}/feed.xml", port),
+            auth: Some(AuthConfig::Bearer {
+                token: SecretRef::Env {
+                    env: "PG_TEST_AUTH_TOKEN".to_string(),
+                },
+            }),
+            ..Default::default()
+        };
+        let http = HttpClient::new(&HttpConfig::default())
+            .unwrap()
+            .for_podcast(&podcast)
+            .unwrap();
+
+        let same_host = http.get_bytes(&podcast.url).await.unwrap();
+        assert!(String::from_utf8(same_host).unwrap().contains("authorization: bearer s3cret"));
+
+        let other_host = http
+            .get_bytes(&format!("http://127.0.0.1:{}/episode.mp3", port))
+            .await
+            .unwrap();
+        assert!(!String::from_utf8(other_host).unwrap().contains("authorization"));
+    }
+}
@@ -1,49 +1,180 @@
-use anyhow::{Context, Result};
+use image::imageops::FilterType;
 use image::io::Reader as ImageReader;
+use image::DynamicImage;
+use sha2::{Digest, Sha256};
 use std::io::Cursor;
 use std::path::{Path, PathBuf};
 
-/// Download and convert image to PNG, returning path to the saved image
+use crate::config::{Config, CoverConfig, CoverFormat};
+use crate::error::PgError;
+use crate::http::HttpClient;
+
+/// Cover art saved to disk, with the ETag the server sent for it
+pub struct SavedImage {
+    pub path: PathBuf,
+    pub etag: Option<String>,
+}
+
+/// Download an image and save it at `file_path` in the given format.
+/// Given the `etag` of an earlier download, returns `None` without
+/// touching the saved image if it hasn't changed since.
 pub async fn download_and_convert_image(
+    http: &HttpClient,
     image_url: &str,
-    output_dir: &Path,
-    podcast_name: &str,
-) -> Result<PathBuf> {
+    file_path: &Path,
+    format: CoverFormat,
+    etag: Option<&str>,
+) -> Result<Option<SavedImage>, PgError> {
     // Download the image
-    let image_data = reqwest::get(image_url)
-        .await
-        .context("Failed to download image")?
-        .bytes()
-        .await
-        .context("Failed to read image content")?;
-
-    // Parse the image to validate and potentially convert it
-    let img = ImageReader::new(Cursor::new(&image_data))
-        .with_guessed_format()
-        .context("Failed to read image data")?
-        .decode()
-        .context("Failed to decode image")?;
+    let Some(fetched) = http.fetch_if_changed(image_url, etag).await? else {
+        return Ok(None);
+    };
+    let encoded = encode(&decode(&fetched.body)?, format, DEFAULT_JPEG_QUALITY)?;
+
+    // The output directory doesn't exist yet on a podcast's first sync
+    if let Some(output_dir) = file_path.parent() {
+        std::fs::create_dir_all(output_dir)
+            .map_err(|e| PgError::filesystem("create output directory", output_dir, e))?;
+    }
+    write_file(file_path, &encoded, "write cover art")?;
 
-    // Convert to PNG and save
-    let filename = format!("{}_cover.png", sanitize_filename(podcast_name));
-    let file_path = output_dir.join(&filename);
+    tracing::debug!("Downloaded and converted image {} to {}", image_url, file_path.display());
+
+    Ok(Some(SavedImage {
+        path: file_path.to_path_buf(),
+        etag: fetched.etag,
+    }))
+}
 
-    img.save_with_format(&file_path, image::ImageFormat::Png)
-        .context("Failed to save image as PNG")?;
+/// Download an episode's own artwork, converted to PNG. Images are cached by
+/// a hash of their URL, so episodes sharing artwork only download it once.
+pub async fn cached_episode_image(http: &HttpClient, image_url: &str) -> Result<PathBuf, PgError> {
+    let cache_dir = cache_dir()?;
+    let file_path = cache_dir.join(format!("{}.png", hex::encode(Sha256::digest(image_url))));
+    if file_path.is_file() {
+        tracing::debug!("Using cached episode image {}", file_path.display());
+        return Ok(file_path);
+    }
 
+    let image_data = http.get_bytes(image_url).await?;
+    let png = encode(&decode(&image_data)?, CoverFormat::Png, DEFAULT_JPEG_QUALITY)?;
+    std::fs::create_dir_all(&cache_dir)
+        .map_err(|e| PgError::filesystem("create image cache", &cache_dir, e))?;
+    write_file(&file_path, &png, "write episode image")?;
+
+    tracing::debug!("Downloaded episode image {} to {}", image_url, file_path.display());
+    Ok(file_path)
+}
+
+/// Where episode artwork and embed copies are cached
+fn cache_dir() -> Result<PathBuf, PgError> {
+    Ok(Config::cache_dir().map_err(PgError::config)?.join("images"))
+}
+
+/// Delete every cached episode image and embed copy. They are downloaded
+/// or made again the next time an episode needs them.
+pub fn clear_cache() -> Result<(), PgError> {
+    let cache_dir = cache_dir()?;
+    match std::fs::remove_dir_all(&cache_dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(PgError::filesystem("remove image cache", &cache_dir, e))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Write an image under a temporary name and rename it into place, so an
+/// interrupted write never leaves a truncated file that looks cached
+fn write_file(file_path: &Path, contents: &[u8], action: &'static str) -> Result<(), PgError> {
+    let mut temp_name = file_path.as_os_str().to_owned();
+    temp_name.push(".partial");
+    let temp_path = PathBuf::from(temp_name);
+
+    std::fs::write(&temp_path, contents).map_err(|e| PgError::filesystem(action, &temp_path, e))?;
+    std::fs::rename(&temp_path, file_path).map_err(|e| {
+        let _ = std::fs::remove_file(&temp_path);
+        PgError::filesystem(action, file_path, e)
+    })
+}
+
+/// JPEG quality used when the config doesn't set one
+const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+/// Make the copy of some cover art that gets embedded, cropped, resized and
+/// re-encoded as configured. Copies are cached by the artwork's contents
+/// and the settings, so each is only made once. Returns the artwork itself
+/// if there is nothing to change.
+pub fn embed_copy(cover_path: &Path, settings: &CoverConfig) -> Result<PathBuf, PgError> {
+    if settings.is_empty() {
+        return Ok(cover_path.to_path_buf());
+    }
+
+    let original = std::fs::read(cover_path).map_err(|e| PgError::filesystem("read cover art", cover_path, e))?;
+    let format = settings.cover_format.unwrap_or(CoverFormat::Png);
+    let quality = settings.cover_quality.unwrap_or(DEFAULT_JPEG_QUALITY).clamp(1, 100);
+    let square = settings.cover_square.unwrap_or(false);
+
+    let mut hasher = Sha256::new();
+    hasher.update(&original);
+    hasher.update(format!("{:?}/{:?}/{}/{}", settings.cover_max_size, format, quality, square));
+    let extension = match format {
+        CoverFormat::Jpeg => "jpg",
+        CoverFormat::Png => "png",
+    };
+    let cache_dir = cache_dir()?.join("embed");
+    let file_path = cache_dir.join(format!("{}.{}", hex::encode(hasher.finalize()), extension));
+    if file_path.is_file() {
+        return Ok(file_path);
+    }
+
+    let mut img = decode(&original)?;
+    if square && img.width() != img.height() {
+        let side = img.width().min(img.height());
+        img = img.crop_imm((img.width() - side) / 2, (img.height() - side) / 2, side, side);
+    }
+    if let Some(max) = settings.cover_max_size.filter(|max| *max > 0) {
+        // Only ever shrink artwork
+        if img.width() > max || img.height() > max {
+            img = img.resize(max, max, FilterType::Lanczos3);
+        }
+    }
+
+    let encoded = encode(&img, format, quality)?;
+    std::fs::create_dir_all(&cache_dir)
+        .map_err(|e| PgError::filesystem("create image cache", &cache_dir, e))?;
+    write_file(&file_path, &encoded, "write cover art")?;
     tracing::debug!(
-        "Downloaded and converted image for '{}' to {}",
-        podcast_name,
+        "Made {}x{} embed copy of {} at {}",
+        img.width(),
+        img.height(),
+        cover_path.display(),
         file_path.display()
     );
-
     Ok(file_path)
 }
 
-/// Sanitize podcast name for use in filename
-fn sanitize_filename(name: &str) -> String {
-    name
-        .chars()
-        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
-        .collect()
+/// Decode an image, which also validates it
+fn decode(image_data: &[u8]) -> Result<DynamicImage, PgError> {
+    ImageReader::new(Cursor::new(image_data))
+        .with_guessed_format()
+        .map_err(|e| PgError::parse("image data", e))?
+        .decode()
+        .map_err(|e| PgError::parse("image", e))
+}
+
+/// Encode an image as JPEG, at the given quality, or PNG
+fn encode(img: &DynamicImage, format: CoverFormat, quality: u8) -> Result<Vec<u8>, PgError> {
+    let mut encoded = Vec::new();
+    match format {
+        CoverFormat::Jpeg => {
+            // JPEG has no alpha channel
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut encoded, quality)
+                .encode_image(&DynamicImage::ImageRgb8(img.to_rgb8()))
+                .map_err(|e| PgError::parse("image as JPEG", e))?;
+        }
+        CoverFormat::Png => img
+            .write_to(&mut Cursor::new(&mut encoded), image::ImageFormat::Png)
+            .map_err(|e| PgError::parse("image as PNG", e))?,
+    }
+    Ok(encoded)
 }
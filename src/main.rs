@@ -1,15 +1,26 @@
+mod chapters;
 mod cli;
 mod config;
 mod download;
+mod error;
 mod feed;
+mod filter;
+mod http;
 mod image;
+mod output;
+mod processing;
+mod replaygain;
+mod secrets;
 mod state;
 mod tagger;
+mod transcripts;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
+use error::ExitStatus;
+use output::OutputFormat;
 use std::path::PathBuf;
-use tracing_subscriber;
+use std::process::ExitCode;
 
 #[derive(Parser)]
 #[command(name = "podcast-getter")]
@@ -21,6 +32,10 @@ struct Cli {
     /// Enable debug logging
     #[arg(global = true, short, long)]
     debug: bool,
+
+    /// Output format for command results
+    #[arg(global = true, long, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
 }
 
 #[derive(Subcommand)]
@@ -30,6 +45,10 @@ enum Commands {
         /// Maximum number of episodes to download per podcast
         #[arg(short, long)]
         max_episodes: Option<usize>,
+
+        /// Stop at the first failed feed or episode
+        #[arg(long)]
+        fail_fast: bool,
     },
 
     /// Add a new podcast feed
@@ -54,20 +73,80 @@ enum Commands {
     /// Show last-check timestamps for all podcasts
     Status,
 
+    /// List the episodes in a podcast feed
+    #[command(about = "List the episodes in a podcast feed and their download status")]
+    Episodes {
+        /// Name of the podcast
+        #[arg(value_name = "NAME")]
+        name: String,
+
+        /// Only show episodes whose title contains this text
+        #[arg(short, long)]
+        search: Option<String>,
+
+        /// Only show episodes published on or after this date (YYYY-MM-DD)
+        #[arg(long, value_name = "DATE")]
+        since: Option<chrono::NaiveDate>,
+
+        /// Maximum number of episodes to show
+        #[arg(short, long)]
+        limit: Option<usize>,
+
+        /// Use the cached feed instead of fetching it
+        #[arg(long)]
+        cached: bool,
+
+        /// Show why each episode would be kept or skipped by the podcast's filters
+        #[arg(long)]
+        explain: bool,
+
+        /// Output as JSON (same as `--output json`)
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Download a specific episode
+    #[command(about = "Download a specific episode from a podcast")]
+    Get {
+        /// Name of the podcast
+        #[arg(value_name = "NAME")]
+        name: String,
+
+        /// Episode GUID, index from `pg episodes`, date (YYYY-MM-DD), or title substring
+        #[arg(value_name = "SELECTOR")]
+        selector: String,
+    },
+
+    /// Download archived episodes from a podcast
+    #[command(about = "Download archived episodes from a podcast")]
+    Backfill {
+        /// Name of the podcast
+        #[arg(value_name = "NAME")]
+        name: String,
+
+        /// Download episodes published on or after this date (YYYY-MM-DD)
+        #[arg(long, value_name = "DATE")]
+        since: chrono::NaiveDate,
+    },
+
     /// Update a specific podcast feed
     #[command(about = "Check and download new episodes from a specific podcast")]
     UpdateFeed {
         /// Name of the podcast to update
         #[arg(value_name = "NAME")]
         name: String,
+
+        /// Stop at the first failed episode
+        #[arg(long)]
+        fail_fast: bool,
     },
 
     /// Create example config file
     #[command(about = "Generate an example config file")]
     InitConfig,
 
-    /// Remove all downloaded MP3 files while keeping cover art
-    #[command(about = "Remove all downloaded MP3 files from configured podcasts")]
+    /// Remove all downloaded episode files while keeping cover art
+    #[command(about = "Remove all downloaded episode files from configured podcasts")]
     Clean,
 
     /// Pause a podcast so it is skipped during download
@@ -105,7 +184,7 @@ enum Commands {
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
 
     // Initialize tracing
@@ -115,34 +194,59 @@ async fn main() -> Result<()> {
         "info"
     };
 
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(filter_level)),
-        )
-        .init();
+    let subscriber = tracing_subscriber::fmt().with_env_filter(
+        tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(filter_level)),
+    );
 
-    match cli.command {
-        Commands::Download { max_episodes } => {
-            cli::download_all_podcasts(max_episodes).await?;
+    // Keep stdout clean for JSON output
+    if cli.output == OutputFormat::Table {
+        subscriber.init();
+    } else {
+        subscriber.with_writer(std::io::stderr).init();
+    }
+
+    match run(cli.command, cli.output).await {
+        Ok(status) => status.into(),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitStatus::for_error(&e).into()
+        }
+    }
+}
+
+async fn run(command: Commands, output: OutputFormat) -> Result<ExitStatus> {
+    match command {
+        Commands::Download { max_episodes, fail_fast } => {
+            return cli::download_all_podcasts(max_episodes, fail_fast, output).await;
         }
         Commands::Add { url, name, output_dir } => {
             cli::add_podcast(url, name, output_dir)?;
         }
         Commands::List => {
-            cli::list_podcasts()?;
+            cli::list_podcasts(output)?;
         }
         Commands::Status => {
-            cli::show_status()?;
+            cli::show_status(output)?;
+        }
+        Commands::Episodes { name, search, since, limit, cached, explain, json } => {
+            let output = if json { OutputFormat::Json } else { output };
+            cli::list_episodes(name, search, since, limit, cached, explain, output).await?;
+        }
+        Commands::Get { name, selector } => {
+            cli::get_episode(name, selector, output).await?;
+        }
+        Commands::Backfill { name, since } => {
+            return cli::backfill_podcast(name, since, output).await;
         }
-        Commands::UpdateFeed { name } => {
-            cli::update_feed(name).await?;
+        Commands::UpdateFeed { name, fail_fast } => {
+            return cli::update_feed(name, fail_fast, output).await;
         }
         Commands::InitConfig => {
             config::Config::create_example()?;
         }
         Commands::Clean => {
-            cli::clean_podcasts()?;
+            cli::clean_podcasts(output)?;
         }
         Commands::Pause { name, all } => {
             cli::pause_podcast(name, all)?;
@@ -158,5 +262,5 @@ async fn main() -> Result<()> {
         }
     }
 
-    Ok(())
+    Ok(ExitStatus::Success)
 }
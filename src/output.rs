@@ -0,0 +1,101 @@
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Version of the JSON output schema, bumped when a field is removed or
+/// changes meaning. New fields may be added without a bump.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// How commands print their results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Table,
+    /// One JSON document when the command finishes
+    Json,
+    /// One JSON object per line, printed as things happen
+    Ndjson,
+}
+
+/// A command's structured output, streamed as NDJSON or collected into a
+/// single JSON document. In table mode nothing is printed here and the
+/// command prints its own text.
+pub struct Output {
+    format: OutputFormat,
+    command: &'static str,
+    records: Vec<Value>,
+    summary: Option<Value>,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat, command: &'static str) -> Self {
+        Output {
+            format,
+            command,
+            records: Vec::new(),
+            summary: None,
+        }
+    }
+
+    /// Whether the command should print human-readable text
+    pub fn is_table(&self) -> bool {
+        self.format == OutputFormat::Table
+    }
+
+    /// Emit one record of the given type
+    pub fn record(&mut self, kind: &str, record: &impl Serialize) -> Result<()> {
+        match self.format {
+            OutputFormat::Table => {}
+            OutputFormat::Json => self.records.push(tagged(kind, record, false)?),
+            OutputFormat::Ndjson => println!("{}", serde_json::to_string(&tagged(kind, record, true)?)?),
+        }
+        Ok(())
+    }
+
+    /// Set the command's closing summary
+    pub fn summary(&mut self, summary: &impl Serialize) -> Result<()> {
+        match self.format {
+            OutputFormat::Table => {}
+            OutputFormat::Json => self.summary = Some(serde_json::to_value(summary)?),
+            OutputFormat::Ndjson => {
+                println!("{}", serde_json::to_string(&tagged("summary", summary, true)?)?)
+            }
+        }
+        Ok(())
+    }
+
+    /// Print the JSON document, if that's the format in use
+    pub fn finish(self) -> Result<()> {
+        if self.format != OutputFormat::Json {
+            return Ok(());
+        }
+
+        let mut document = Map::new();
+        document.insert("version".to_string(), SCHEMA_VERSION.into());
+        document.insert("command".to_string(), self.command.into());
+        document.insert("records".to_string(), Value::Array(self.records));
+        if let Some(summary) = self.summary {
+            document.insert("summary".to_string(), summary);
+        }
+        println!("{}", serde_json::to_string_pretty(&document)?);
+        Ok(())
+    }
+}
+
+/// Serialize a record with its `type` first, and the schema version when it
+/// stands alone as an NDJSON line
+fn tagged(kind: &str, record: &impl Serialize, versioned: bool) -> Result<Value> {
+    let mut object = Map::new();
+    if versioned {
+        object.insert("version".to_string(), SCHEMA_VERSION.into());
+    }
+    object.insert("type".to_string(), kind.into());
+    match serde_json::to_value(record)? {
+        Value::Object(fields) => object.extend(fields),
+        other => {
+            object.insert("value".to_string(), other);
+        }
+    }
+    Ok(Value::Object(object))
+}
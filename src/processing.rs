@@ -0,0 +1,147 @@
+//! Post-processing of downloaded episodes with a locally installed ffmpeg:
+//! loudness normalization, downmixing, re-encoding and transcoding
+
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+use tokio::process::Command;
+
+use crate::config::ProcessingSettings;
+use crate::error::PgError;
+
+/// A processed episode
+pub struct Processed {
+    /// Where the episode is now, which changes when it was transcoded
+    pub path: PathBuf,
+    /// The file as downloaded, if it was kept
+    pub original: Option<PathBuf>,
+}
+
+/// Run a downloaded episode through ffmpeg. The result replaces the download
+/// unless the original is kept, in which case an original with the same
+/// extension is renamed to `<episode>.original.<ext>`. If ffmpeg isn't
+/// installed the episode is left as downloaded.
+pub async fn process_episode(file_path: &Path, settings: &ProcessingSettings) -> Result<Processed, PgError> {
+    let unchanged = || Processed {
+        path: file_path.to_path_buf(),
+        original: None,
+    };
+    let processing_error = |message: String| PgError::Processing {
+        path: file_path.to_path_buf(),
+        message,
+    };
+
+    let extension = match settings.format {
+        Some(format) => format.extension().to_string(),
+        None => file_path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_else(|| "mp3".to_string()),
+    };
+    let output_path = file_path.with_extension(&extension);
+    // ffmpeg picks the container from the extension, so it goes last
+    let temp_path = file_path.with_extension(format!("processing.{}", extension));
+
+    let mut cmd = Command::new(&settings.ffmpeg);
+    cmd.args(["-hide_banner", "-nostdin", "-loglevel", "error", "-y", "-i"])
+        .arg(file_path)
+        // Only the audio; cover art and chapters are written when tagging
+        .args(["-map", "0:a:0"]);
+    if let Some(loudnorm) = settings.loudnorm {
+        cmd.arg("-af").arg(format!(
+            "loudnorm=I={}:TP={}:LRA=11",
+            loudnorm.target, loudnorm.true_peak
+        ));
+        // loudnorm works at 192 kHz, far more than speech needs
+        cmd.args(["-ar", "48000"]);
+    }
+    if settings.mono {
+        cmd.args(["-ac", "1"]);
+    }
+    let codec = match settings.format {
+        Some(_) => codec_for(&extension),
+        // Re-encoded in the codec it came in
+        None if matches!(extension.as_str(), "ogg" | "oga") => ogg_codec(file_path),
+        None => codec_for(&extension),
+    };
+    if let Some(codec) = codec {
+        cmd.args(["-c:a", codec]);
+    }
+    if let Some(bitrate) = &settings.bitrate {
+        cmd.arg("-b:a").arg(bitrate);
+    }
+    cmd.arg(&temp_path);
+
+    let output = match cmd.output().await {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            tracing::warn!(
+                "{} not found. Skipping processing for {}",
+                settings.ffmpeg.display(),
+                file_path.display()
+            );
+            return Ok(unchanged());
+        }
+        Err(e) => return Err(processing_error(format!("failed to run ffmpeg: {}", e))),
+    };
+    if !output.status.success() {
+        let _ = std::fs::remove_file(&temp_path);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(processing_error(format!("ffmpeg failed: {}", stderr.trim())));
+    }
+
+    let original = if !settings.keep_original {
+        if output_path != file_path {
+            std::fs::remove_file(file_path).map_err(|e| PgError::filesystem("remove", file_path, e))?;
+        }
+        None
+    } else if output_path == file_path {
+        let kept = file_path.with_extension(format!("original.{}", extension));
+        std::fs::rename(file_path, &kept).map_err(|e| PgError::filesystem("rename", file_path, e))?;
+        Some(kept)
+    } else {
+        Some(file_path.to_path_buf())
+    };
+    std::fs::rename(&temp_path, &output_path).map_err(|e| {
+        let _ = std::fs::remove_file(&temp_path);
+        PgError::filesystem("rename", &temp_path, e)
+    })?;
+
+    tracing::debug!("Processed {} into {}", file_path.display(), output_path.display());
+    Ok(Processed {
+        path: output_path,
+        original,
+    })
+}
+
+/// The ffmpeg encoder for a file extension, if ffmpeg's default for it
+/// isn't right or there is no obvious one
+fn codec_for(extension: &str) -> Option<&'static str> {
+    match extension {
+        "mp3" => Some("libmp3lame"),
+        "m4a" | "m4b" | "mp4" | "aac" => Some("aac"),
+        "opus" => Some("libopus"),
+        "ogg" | "oga" => Some("libvorbis"),
+        "flac" => Some("flac"),
+        _ => None,
+    }
+}
+
+/// The ffmpeg encoder for the codec inside an Ogg file, which could be
+/// Vorbis, Opus or FLAC, from the start of its first packet
+fn ogg_codec(file_path: &Path) -> Option<&'static str> {
+    let mut header = [0u8; 64];
+    let len = File::open(file_path).and_then(|mut file| file.read(&mut header)).ok()?;
+    let header = &header[..len];
+    let contains = |magic: &[u8]| header.windows(magic.len()).any(|window| window == magic);
+    if contains(b"OpusHead") {
+        Some("libopus")
+    } else if contains(b"\x01vorbis") {
+        Some("libvorbis")
+    } else if contains(b"\x7fFLAC") {
+        Some("flac")
+    } else {
+        None
+    }
+}
@@ -0,0 +1,425 @@
+//! ReplayGain 2.0 analysis: decode an episode and measure its EBU R128
+//! integrated loudness and sample peak
+
+use std::f64::consts::PI;
+use std::fs::File;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::error::PgError;
+use crate::state::State;
+
+/// ReplayGain 2.0's reference loudness, in LUFS
+const REFERENCE_LOUDNESS: f64 = -18.0;
+
+/// Blocks quieter than this, in LUFS, are left out of the integrated loudness
+const ABSOLUTE_GATE: f64 = -70.0;
+
+/// Then so are blocks this many LU below the loudness of those that are left
+const RELATIVE_GATE: f64 = -10.0;
+
+/// A track's ReplayGain values
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReplayGain {
+    /// Gain to reach the reference loudness, in dB
+    pub track_gain: f64,
+    /// Highest sample value, where 1.0 is full scale
+    pub track_peak: f64,
+}
+
+impl ReplayGain {
+    /// The gain as written to tags, e.g. `-3.21 dB`
+    pub fn gain_text(&self) -> String {
+        format!("{:.2} dB", self.track_gain)
+    }
+
+    pub fn peak_text(&self) -> String {
+        format!("{:.6}", self.track_peak)
+    }
+}
+
+/// Measure an episode's ReplayGain, along with a hash of the audio it was
+/// measured from. Values measured earlier from the same audio are reused.
+pub async fn measure(
+    file_path: &Path,
+    podcast_name: &str,
+    episode_id: &str,
+    state: &State,
+) -> Option<(ReplayGain, String)> {
+    let warn = |e: PgError| tracing::warn!("Failed to measure ReplayGain for {}: {}", file_path.display(), e);
+
+    let sha256 = match blocking(file_path, hash_file).await {
+        Ok(sha256) => sha256,
+        Err(e) => {
+            warn(e);
+            return None;
+        }
+    };
+    if let Some(values) = state.replaygain_for(podcast_name, episode_id, &sha256) {
+        tracing::debug!("Reusing ReplayGain measured earlier for {}", file_path.display());
+        return Some((values, sha256));
+    }
+    match blocking(file_path, analyze).await {
+        Ok(Some(values)) => {
+            tracing::debug!(
+                "ReplayGain for {}: {}, peak {}",
+                file_path.display(),
+                values.gain_text(),
+                values.peak_text()
+            );
+            Some((values, sha256))
+        }
+        Ok(None) => None,
+        Err(e) => {
+            warn(e);
+            None
+        }
+    }
+}
+
+/// Run file work that would hold up the async runtime, such as decoding a
+/// whole episode, on the blocking thread pool
+async fn blocking<T: Send + 'static>(
+    file_path: &Path,
+    work: fn(&Path) -> Result<T, PgError>,
+) -> Result<T, PgError> {
+    let path = file_path.to_path_buf();
+    tokio::task::spawn_blocking(move || work(&path))
+        .await
+        .map_err(|e| PgError::Processing {
+            path: file_path.to_path_buf(),
+            message: e.to_string(),
+        })?
+}
+
+/// SHA-256 of a file's contents, in hex
+fn hash_file(path: &Path) -> Result<String, PgError> {
+    let mut file = File::open(path).map_err(|e| PgError::filesystem("open", path, e))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).map_err(|e| PgError::filesystem("read", path, e))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Decode an episode and work out its ReplayGain. Returns `None` for formats
+/// that can't be decoded natively, such as Opus, and for silent files.
+fn analyze(file_path: &Path) -> Result<Option<ReplayGain>, PgError> {
+    let parse_error = |e: SymphoniaError| PgError::parse("audio", e);
+
+    let file = File::open(file_path).map_err(|e| PgError::filesystem("open", file_path, e))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = file_path.extension() {
+        hint.with_extension(&extension.to_string_lossy());
+    }
+    let probed = match symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    ) {
+        Ok(probed) => probed,
+        Err(SymphoniaError::Unsupported(what)) => {
+            tracing::debug!("Can't analyze {}: unsupported {}", file_path.display(), what);
+            return Ok(None);
+        }
+        Err(e) => return Err(parse_error(e)),
+    };
+    let mut format = probed.format;
+
+    let Some(track) = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+    else {
+        return Ok(None);
+    };
+    let track_id = track.id;
+    let Some(sample_rate) = track.codec_params.sample_rate else {
+        return Ok(None);
+    };
+    let mut decoder = match symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default()) {
+        Ok(decoder) => decoder,
+        Err(SymphoniaError::Unsupported(what)) => {
+            tracing::debug!("Can't analyze {}: unsupported {}", file_path.display(), what);
+            return Ok(None);
+        }
+        Err(e) => return Err(parse_error(e)),
+    };
+
+    let mut meter: Option<LoudnessMeter> = None;
+    let mut samples: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(SymphoniaError::ResetRequired) => break,
+            Err(e) => return Err(parse_error(e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A damaged frame is skipped, as players do
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(parse_error(e)),
+        };
+
+        let spec = *decoded.spec();
+        let buffer = match &mut samples {
+            Some(buffer) if buffer.capacity() >= decoded.capacity() * spec.channels.count() => buffer,
+            _ => samples.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+        };
+        buffer.copy_interleaved_ref(decoded);
+        meter
+            .get_or_insert_with(|| LoudnessMeter::new(sample_rate, spec.channels.count()))
+            .add(buffer.samples());
+    }
+
+    Ok(meter.and_then(|meter| {
+        let loudness = meter.integrated_loudness()?;
+        Some(ReplayGain {
+            track_gain: REFERENCE_LOUDNESS - loudness,
+            track_peak: meter.peak,
+        })
+    }))
+}
+
+/// One second-order IIR filter section
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The two K-weighting filters from ITU-R BS.1770, worked out for any
+/// sample rate: a high shelf for the head, then a high-pass
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+/// Measures integrated loudness as in EBU R128: K-weighted energy over
+/// 400 ms blocks overlapping by 75%, gated at -70 LUFS and then 10 LU
+/// below the ungated loudness. Every channel is weighted equally, which is
+/// right for the mono and stereo files podcasts come in.
+struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    /// Frames in each 100 ms step
+    step_len: usize,
+    /// Summed energy of each complete 100 ms step
+    steps: Vec<f64>,
+    current: f64,
+    current_len: usize,
+    peak: f64,
+}
+
+impl LoudnessMeter {
+    fn new(sample_rate: u32, channels: usize) -> Self {
+        LoudnessMeter {
+            channels,
+            filters: vec![k_weighting(sample_rate); channels],
+            step_len: (sample_rate as usize / 10).max(1),
+            steps: Vec::new(),
+            current: 0.0,
+            current_len: 0,
+            peak: 0.0,
+        }
+    }
+
+    /// Feed interleaved samples
+    fn add(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (sample, filters) in frame.iter().zip(&mut self.filters) {
+                let x = *sample as f64;
+                self.peak = self.peak.max(x.abs());
+                let [shelf, high_pass] = filters;
+                let y = high_pass.process(shelf.process(x));
+                self.current += y * y;
+            }
+            self.current_len += 1;
+            if self.current_len == self.step_len {
+                self.steps.push(self.current);
+                self.current = 0.0;
+                self.current_len = 0;
+            }
+        }
+    }
+
+    /// Integrated loudness in LUFS, or `None` if nothing was above the
+    /// gates, such as for silence or a file shorter than one block
+    fn integrated_loudness(&self) -> Option<f64> {
+        let block_len = (self.step_len * 4) as f64;
+        let blocks: Vec<f64> = self
+            .steps
+            .windows(4)
+            .map(|steps| steps.iter().sum::<f64>() / block_len)
+            .collect();
+        let loudness = |energy: f64| -0.691 + 10.0 * energy.log10();
+        let mean = |energies: &[f64]| energies.iter().sum::<f64>() / energies.len() as f64;
+
+        let absolute: Vec<f64> = blocks.into_iter().filter(|&z| loudness(z) > ABSOLUTE_GATE).collect();
+        if absolute.is_empty() {
+            return None;
+        }
+        let relative_gate = loudness(mean(&absolute)) + RELATIVE_GATE;
+        let gated: Vec<f64> = absolute.into_iter().filter(|&z| loudness(z) > relative_gate).collect();
+        if gated.is_empty() {
+            return None;
+        }
+        Some(loudness(mean(&gated)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 997 Hz sine at the given amplitude, where 1.0 is full scale
+    fn sine(sample_rate: u32, amplitude: f64, seconds: f64) -> Vec<f32> {
+        let frames = (sample_rate as f64 * seconds) as usize;
+        (0..frames)
+            .map(|i| (amplitude * (2.0 * PI * 997.0 * i as f64 / sample_rate as f64).sin()) as f32)
+            .collect()
+    }
+
+    fn measure_mono(samples: &[f32], sample_rate: u32) -> Option<f64> {
+        let mut meter = LoudnessMeter::new(sample_rate, 1);
+        meter.add(samples);
+        meter.integrated_loudness()
+    }
+
+    /// Amplitude of a 997 Hz sine measuring `lufs`. Full scale measures
+    /// -3.01 LUFS in one channel, per BS.1770.
+    fn amplitude_for(lufs: f64) -> f64 {
+        10f64.powf((lufs + 3.01) / 20.0)
+    }
+
+    #[test]
+    fn full_scale_sine_measures_minus_3_lufs() {
+        for sample_rate in [44_100, 48_000] {
+            let loudness = measure_mono(&sine(sample_rate, 1.0, 5.0), sample_rate).unwrap();
+            assert!((loudness + 3.01).abs() < 0.05, "{} Hz measured {}", sample_rate, loudness);
+        }
+    }
+
+    #[test]
+    fn stereo_counts_both_channels() {
+        let mono = sine(48_000, 1.0, 5.0);
+        let stereo: Vec<f32> = mono.iter().flat_map(|&sample| [sample, sample]).collect();
+        let mut meter = LoudnessMeter::new(48_000, 2);
+        meter.add(&stereo);
+        let loudness = meter.integrated_loudness().unwrap();
+        assert!(loudness.abs() < 0.05, "measured {}", loudness);
+        assert!((meter.peak - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn silence_and_short_files_have_no_loudness() {
+        assert_eq!(measure_mono(&vec![0.0; 48_000 * 5], 48_000), None);
+        // Shorter than one 400 ms block
+        assert_eq!(measure_mono(&sine(48_000, 1.0, 0.3), 48_000), None);
+    }
+
+    #[test]
+    fn absolute_gate_drops_blocks_below_minus_70() {
+        let above = amplitude_for(ABSOLUTE_GATE + 5.0);
+        let loudness = measure_mono(&sine(48_000, above, 5.0), 48_000).unwrap();
+        assert!((loudness - (ABSOLUTE_GATE + 5.0)).abs() < 0.05, "measured {}", loudness);
+
+        let below = amplitude_for(ABSOLUTE_GATE - 5.0);
+        assert_eq!(measure_mono(&sine(48_000, below, 5.0), 48_000), None);
+    }
+
+    #[test]
+    fn relative_gate_drops_quiet_passages() {
+        // Loud speech, then a long stretch 40 LU quieter
+        let mut samples = sine(48_000, 1.0, 5.0);
+        samples.extend(sine(48_000, amplitude_for(-43.01), 10.0));
+        let loudness = measure_mono(&samples, 48_000).unwrap();
+        // Without the relative gate the quiet part would pull this to about -7.8
+        assert!((loudness + 3.01).abs() < 0.3, "measured {}", loudness);
+    }
+
+    /// Write mono 16-bit PCM samples to a WAV file
+    fn write_wav(path: &Path, samples: &[f32], sample_rate: u32) {
+        let data_len = samples.len() as u32 * 2;
+        let mut wav = Vec::with_capacity(44 + data_len as usize);
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, one channel
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            wav.extend_from_slice(&((sample * i16::MAX as f32) as i16).to_le_bytes());
+        }
+        std::fs::write(path, wav).unwrap();
+    }
+
+    #[test]
+    fn gain_brings_a_file_to_minus_18_lufs() {
+        let path = std::env::temp_dir().join(format!("pg-replaygain-{}.wav", std::process::id()));
+        // A sine at -23 LUFS, the EBU broadcast target, needs +5 dB
+        let amplitude = amplitude_for(-23.0);
+        write_wav(&path, &sine(48_000, amplitude, 5.0), 48_000);
+        let analyzed = analyze(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let replaygain = analyzed.unwrap().unwrap();
+        assert!((replaygain.track_gain - 5.0).abs() < 0.05, "gain {}", replaygain.track_gain);
+        assert!((replaygain.track_peak - amplitude).abs() < 1e-3, "peak {}", replaygain.track_peak);
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::Config;
+
+/// A reference to a secret kept outside config.toml, so the config can be
+/// shared or printed without leaking credentials
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SecretRef {
+    /// Read from an environment variable: `{ env = "PATREON_TOKEN" }`
+    Env { env: String },
+    /// Read from an entry in secrets.toml: `{ secret = "patreon" }`
+    File { secret: String },
+}
+
+impl SecretRef {
+    /// Look up the secret's value
+    pub fn resolve(&self) -> Result<String> {
+        match self {
+            SecretRef::Env { env } => std::env::var(env)
+                .with_context(|| format!("Environment variable {} is not set", env)),
+            SecretRef::File { secret } => {
+                let secrets = load_secrets()?;
+                secrets.get(secret).cloned().with_context(|| {
+                    format!(
+                        "No secret named '{}' in {}",
+                        secret,
+                        secrets_file().map(|p| p.display().to_string()).unwrap_or_default()
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// Describes where the secret comes from, never its value
+impl fmt::Display for SecretRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretRef::Env { env } => write!(f, "env {}", env),
+            SecretRef::File { secret } => write!(f, "secret '{}'", secret),
+        }
+    }
+}
+
+/// Get the secrets file path (~/.config/podcast-getter/secrets.toml)
+pub fn secrets_file() -> Result<PathBuf> {
+    Ok(Config::config_dir()?.join("secrets.toml"))
+}
+
+/// Load the secrets file, a flat table of names to values.
+///
+/// The file must not be readable by other users.
+fn load_secrets() -> Result<BTreeMap<String, String>> {
+    let path = secrets_file()?;
+
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read secrets file {}", path.display()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&path)
+            .context("Failed to read secrets file permissions")?
+            .permissions()
+            .mode();
+        if mode & 0o077 != 0 {
+            anyhow::bail!(
+                "Secrets file {} is accessible by other users; run `chmod 600 {}`",
+                path.display(),
+                path.display()
+            );
+        }
+    }
+
+    toml::from_str(&content).context("Failed to parse secrets file")
+}
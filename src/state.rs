@@ -2,14 +2,99 @@ use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use fs2::FileExt;
 use std::fs;
+use std::path::PathBuf;
 
 use crate::config::Config;
+use crate::error::PgError;
+use crate::feed::Episode;
+use crate::replaygain::ReplayGain;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct State {
     /// Map of podcast name to last-check timestamp
     pub last_checks: HashMap<String, DateTime<Utc>>,
+    /// Map of podcast name to the episodes `pg` has recorded, keyed by episode id
+    #[serde(default)]
+    pub episodes: HashMap<String, HashMap<String, EpisodeRecord>>,
+    /// When each episode without a parseable date was first seen, by podcast and episode id
+    #[serde(default)]
+    pub first_seen: HashMap<String, HashMap<String, DateTime<Utc>>>,
+    /// Number of episodes with unparseable dates in each podcast's last fetched feed
+    #[serde(default)]
+    pub undated_counts: HashMap<String, usize>,
+    /// Cover art saved for each podcast, by podcast name
+    #[serde(default)]
+    pub cover_art: HashMap<String, CoverArtRecord>,
+}
+
+/// Where a podcast's cover art came from, to tell whether it needs
+/// downloading again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoverArtRecord {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    pub file: PathBuf,
+}
+
+/// What `pg` knows about a single episode it has handled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EpisodeRecord {
+    pub title: String,
+    #[serde(default)]
+    pub status: EpisodeStatus,
+    /// Why the download failed, for failed episodes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// File the episode was saved to, cleared once the file is cleaned up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    /// Transcript saved next to the file, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcript: Option<PathBuf>,
+    /// The file as downloaded, when it was kept after post-processing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original: Option<PathBuf>,
+    /// ReplayGain measured for the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaygain: Option<ReplayGainRecord>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// ReplayGain values, with a hash of the audio they were measured from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayGainRecord {
+    #[serde(flatten)]
+    pub values: ReplayGain,
+    /// SHA-256 of the file as analyzed, before it was tagged
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EpisodeStatus {
+    #[default]
+    Downloaded,
+    Failed,
+    /// Passed over during a download run, e.g. because of `max_episodes`
+    Skipped,
+}
+
+impl EpisodeStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EpisodeStatus::Downloaded => "downloaded",
+            EpisodeStatus::Failed => "failed",
+            EpisodeStatus::Skipped => "skipped",
+        }
+    }
+}
+
+/// Guard for the state lock, released when dropped
+pub struct StateLock {
+    _file: fs::File,
 }
 
 impl State {
@@ -18,6 +103,22 @@ impl State {
         Ok(Config::config_dir()?.join("state.json"))
     }
 
+    /// Take the lock that keeps two instances from writing state at once.
+    /// It is held until the returned guard is dropped.
+    pub fn lock() -> Result<StateLock> {
+        let lock_path = Config::config_dir()?.join("state.lock");
+
+        fs::create_dir_all(Config::config_dir()?)
+            .context("Failed to create config directory")?;
+        let file = fs::File::create(&lock_path)
+            .context("Failed to create state lock file")?;
+        if file.try_lock_exclusive().is_err() {
+            return Err(PgError::Locked(lock_path).into());
+        }
+
+        Ok(StateLock { _file: file })
+    }
+
     /// Load state from file
     pub fn load() -> Result<Self> {
         let state_path = Self::state_file()?;
@@ -57,4 +158,225 @@ impl State {
     pub fn set_last_check(&mut self, podcast_name: String, time: DateTime<Utc>) {
         self.last_checks.insert(podcast_name, time);
     }
+
+    /// Record that an episode was downloaded to `file`
+    pub fn record_download(
+        &mut self,
+        podcast_name: &str,
+        episode_id: &str,
+        title: &str,
+        file: PathBuf,
+    ) {
+        self.episodes.entry(podcast_name.to_string()).or_default().insert(
+            episode_id.to_string(),
+            EpisodeRecord {
+                title: title.to_string(),
+                status: EpisodeStatus::Downloaded,
+                error: None,
+                file: Some(file),
+                transcript: None,
+                original: None,
+                replaygain: None,
+                recorded_at: Utc::now(),
+            },
+        );
+    }
+
+    /// Record the transcript saved for a downloaded episode
+    pub fn record_transcript(&mut self, podcast_name: &str, episode_id: &str, transcript: PathBuf) {
+        if let Some(record) = self.episodes.get_mut(podcast_name).and_then(|r| r.get_mut(episode_id)) {
+            record.transcript = Some(transcript);
+        }
+    }
+
+    /// Record the original of a post-processed episode
+    pub fn record_original(&mut self, podcast_name: &str, episode_id: &str, original: PathBuf) {
+        if let Some(record) = self.episodes.get_mut(podcast_name).and_then(|r| r.get_mut(episode_id)) {
+            record.original = Some(original);
+        }
+    }
+
+    /// Record the ReplayGain measured for a downloaded episode
+    pub fn record_replaygain(&mut self, podcast_name: &str, episode_id: &str, values: ReplayGain, sha256: String) {
+        if let Some(record) = self.episodes.get_mut(podcast_name).and_then(|r| r.get_mut(episode_id)) {
+            record.replaygain = Some(ReplayGainRecord { values, sha256 });
+        }
+    }
+
+    /// ReplayGain measured earlier for an episode, if it was measured from
+    /// audio with the given hash
+    pub fn replaygain_for(&self, podcast_name: &str, episode_id: &str, sha256: &str) -> Option<ReplayGain> {
+        self.episodes
+            .get(podcast_name)?
+            .get(episode_id)?
+            .replaygain
+            .as_ref()
+            .filter(|record| record.sha256 == sha256)
+            .map(|record| record.values)
+    }
+
+    /// Record that downloading an episode failed. An episode downloaded
+    /// earlier keeps its record, since its files are still there.
+    pub fn record_failure(&mut self, podcast_name: &str, episode_id: &str, title: &str, error: String) {
+        let records = self.episodes.entry(podcast_name.to_string()).or_default();
+        if records.get(episode_id).is_some_and(|record| record.file.is_some()) {
+            return;
+        }
+        records.insert(
+            episode_id.to_string(),
+            EpisodeRecord {
+                title: title.to_string(),
+                status: EpisodeStatus::Failed,
+                error: Some(error),
+                file: None,
+                transcript: None,
+                original: None,
+                replaygain: None,
+                recorded_at: Utc::now(),
+            },
+        );
+    }
+
+    /// Record that an episode was passed over, unless it is already recorded
+    pub fn record_skipped(&mut self, podcast_name: &str, episode_id: &str, title: &str) {
+        self.episodes
+            .entry(podcast_name.to_string())
+            .or_default()
+            .entry(episode_id.to_string())
+            .or_insert_with(|| EpisodeRecord {
+                title: title.to_string(),
+                status: EpisodeStatus::Skipped,
+                error: None,
+                file: None,
+                transcript: None,
+                original: None,
+                replaygain: None,
+                recorded_at: Utc::now(),
+            });
+    }
+
+    /// Give episodes without a parseable date a stable date: the time `pg`
+    /// first saw them. Returns how many episodes needed it.
+    pub fn resolve_undated(&mut self, podcast_name: &str, episodes: &mut [Episode]) -> usize {
+        let mut count = 0;
+        let now = Utc::now();
+        let seen = self.first_seen.entry(podcast_name.to_string()).or_default();
+        for episode in episodes.iter_mut().filter(|ep| !ep.pub_date_known) {
+            episode.pub_date = *seen.entry(episode.id().to_string()).or_insert(now);
+            count += 1;
+        }
+        self.undated_counts.insert(podcast_name.to_string(), count);
+        count
+    }
+
+    /// Number of episodes with unparseable dates in a podcast's last fetched feed
+    pub fn undated_count(&self, podcast_name: &str) -> usize {
+        self.undated_counts.get(podcast_name).copied().unwrap_or(0)
+    }
+
+    /// Cover art last saved for a podcast
+    pub fn cover_art(&self, podcast_name: &str) -> Option<&CoverArtRecord> {
+        self.cover_art.get(podcast_name)
+    }
+
+    /// Record the cover art saved for a podcast
+    pub fn record_cover_art(&mut self, podcast_name: &str, url: &str, etag: Option<String>, file: PathBuf) {
+        self.cover_art.insert(
+            podcast_name.to_string(),
+            CoverArtRecord {
+                url: url.to_string(),
+                etag,
+                file,
+            },
+        );
+    }
+
+    /// Look up the record for an episode of a podcast
+    pub fn episode(&self, podcast_name: &str, episode_id: &str) -> Option<&EpisodeRecord> {
+        self.episodes.get(podcast_name)?.get(episode_id)
+    }
+
+    /// Files recorded as downloaded for a podcast that have not been cleaned yet
+    pub fn downloaded_files(&self, podcast_name: &str) -> Vec<PathBuf> {
+        self.episodes
+            .get(podcast_name)
+            .map(|records| records.values().filter_map(|r| r.file.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Whether pg has recorded downloading any of a podcast's episodes
+    pub fn has_downloads(&self, podcast_name: &str) -> bool {
+        self.episodes
+            .get(podcast_name)
+            .is_some_and(|records| records.values().any(|r| r.status == EpisodeStatus::Downloaded))
+    }
+
+    /// Transcript saved next to a downloaded file, if any
+    pub fn transcript_for(&self, podcast_name: &str, file: &std::path::Path) -> Option<PathBuf> {
+        self.episodes
+            .get(podcast_name)?
+            .values()
+            .find(|r| r.file.as_deref() == Some(file))?
+            .transcript
+            .clone()
+    }
+
+    /// The kept original of the episode saved to `file`, if any
+    pub fn original_for(&self, podcast_name: &str, file: &std::path::Path) -> Option<PathBuf> {
+        self.episodes
+            .get(podcast_name)?
+            .values()
+            .find(|r| r.file.as_deref() == Some(file))?
+            .original
+            .clone()
+    }
+
+    /// Forget the file, its transcript and original, for any episode of a
+    /// podcast that was saved to `file`
+    pub fn clear_file(&mut self, podcast_name: &str, file: &std::path::Path) {
+        if let Some(records) = self.episodes.get_mut(podcast_name) {
+            for record in records.values_mut() {
+                if record.file.as_deref() == Some(file) {
+                    record.file = None;
+                    record.transcript = None;
+                    record.original = None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn failure_keeps_an_earlier_download() {
+        let mut state = State::default();
+        state.record_download("Show", "ep", "Episode", PathBuf::from("/podcasts/ep.mp3"));
+        state.record_original("Show", "ep", PathBuf::from("/podcasts/ep.original.mp3"));
+        state.record_transcript("Show", "ep", PathBuf::from("/podcasts/ep.srt"));
+
+        state.record_failure("Show", "ep", "Episode", "HTTP 404".to_string());
+
+        let record = &state.episodes["Show"]["ep"];
+        assert_eq!(record.status, EpisodeStatus::Downloaded);
+        assert_eq!(record.error, None);
+        assert_eq!(record.file.as_deref(), Some(Path::new("/podcasts/ep.mp3")));
+        assert_eq!(record.original.as_deref(), Some(Path::new("/podcasts/ep.original.mp3")));
+        assert_eq!(record.transcript.as_deref(), Some(Path::new("/podcasts/ep.srt")));
+    }
+
+    #[test]
+    fn failure_replaces_a_skipped_record() {
+        let mut state = State::default();
+        state.record_skipped("Show", "ep", "Episode");
+
+        state.record_failure("Show", "ep", "Episode", "HTTP 404".to_string());
+
+        let record = &state.episodes["Show"]["ep"];
+        assert_eq!(record.status, EpisodeStatus::Failed);
+        assert_eq!(record.error.as_deref(), Some("HTTP 404"));
+    }
 }
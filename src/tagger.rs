@@ -1,79 +1,501 @@
-use anyhow::{Context, Result};
-use std::path::Path;
+mod mp4;
+mod vorbis;
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::OnceLock;
+
+use id3::TagLike;
+use regex::{Captures, Regex};
+
+use crate::chapters::Chapter;
+use crate::config::{PodcastConfig, TagMapping, TaggerBackend};
+use crate::error::PgError;
+use crate::feed::{Episode, FeedInfo};
+use crate::replaygain::ReplayGain;
+
+/// Metadata written to a downloaded episode. Tags left as `None` are not
+/// written, and whatever the file already had is kept.
+#[derive(Debug, Default)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub artist: Option<String>,
+    pub album_artist: Option<String>,
+    /// Release date, as `YYYY-MM-DD` or a year
+    pub date: Option<String>,
+    pub track: Option<u32>,
+    pub disc: Option<u32>,
+    pub genre: Option<String>,
+    pub comment: Option<String>,
+    /// The podcast's feed URL
+    pub podcast_url: Option<String>,
+    /// The episode's GUID
+    pub episode_id: Option<String>,
+    /// Unsynchronised lyrics, used for the episode's transcript
+    pub lyrics: Option<String>,
+    pub cover_art: Option<PathBuf>,
+    /// Chapter markers, from the episode's chapters document
+    pub chapters: Vec<Chapter>,
+    pub replaygain: Option<ReplayGain>,
+}
+
+impl Tags {
+    /// Fill in a podcast's tag templates for one episode
+    pub fn for_episode(
+        mapping: &TagMapping,
+        podcast: &PodcastConfig,
+        feed: &FeedInfo,
+        episode: &Episode,
+    ) -> Result<Tags, PgError> {
+        let values = |name: &str| -> Option<Option<String>> {
+            let value = match name {
+                "podcast" => Some(podcast.name.clone()),
+                "feed_title" => Some(feed.title.clone()),
+                "title" => Some(episode.title.clone()),
+                "date" => Some(episode.pub_date.format("%Y-%m-%d").to_string()),
+                "year" => Some(episode.pub_date.format("%Y").to_string()),
+                "episode" => episode.episode_number.map(|n| n.to_string()),
+                "season" => episode.season.map(|n| n.to_string()),
+                "author" => feed.author.clone(),
+                "category" => feed.categories.first().cloned(),
+                "description" => episode.description.as_deref().map(plain_text),
+                "guid" => Some(episode.id().to_string()),
+                "url" => Some(episode.url.clone()),
+                "link" => feed.link.clone(),
+                "feed_url" => Some(podcast.url.clone()),
+                _ => return None,
+            };
+            Some(value)
+        };
+        let render = |template: &Option<String>| -> Result<Option<String>, PgError> {
+            template
+                .as_deref()
+                .map(|template| render_template(template, values))
+                .transpose()
+                .map(Option::flatten)
+        };
+        let number = |template: &Option<String>| -> Result<Option<u32>, PgError> {
+            Ok(render(template)?.and_then(|value| value.parse().ok()))
+        };
+
+        Ok(Tags {
+            title: render(&mapping.title)?,
+            album: render(&mapping.album)?,
+            artist: render(&mapping.artist)?,
+            album_artist: render(&mapping.album_artist)?,
+            date: render(&mapping.date)?,
+            track: number(&mapping.track)?,
+            disc: number(&mapping.disc)?,
+            genre: render(&mapping.genre)?,
+            comment: render(&mapping.comment)?,
+            podcast_url: render(&mapping.podcast_url)?,
+            episode_id: render(&mapping.episode_id)?,
+            lyrics: None,
+            cover_art: None,
+            chapters: Vec::new(),
+            replaygain: None,
+        })
+    }
+}
 
-/// Try to tag audio file with metadata using audio-metadata
-pub fn tag_audio_file(
-    file_path: &Path,
-    podcast_name: &str,
-    episode_title: &str,
-    cover_art_path: Option<&Path>,
-) -> Result<()> {
-    // Check if audio-metadata is available
-    match Command::new("audio-metadata")
-        .arg("--help")
-        .output()
-    {
-        Ok(_) => {
-            // audio-metadata is available, use it to tag the file
-            run_audio_metadata(file_path, podcast_name, episode_title, cover_art_path)
+/// The placeholders [`Tags::for_episode`] fills in
+const PLACEHOLDERS: &[&str] = &[
+    "podcast",
+    "feed_title",
+    "title",
+    "date",
+    "year",
+    "episode",
+    "season",
+    "author",
+    "category",
+    "description",
+    "guid",
+    "url",
+    "link",
+    "feed_url",
+];
+
+/// Check that a tag template only uses known placeholders
+pub fn check_template(template: &str) -> Result<(), PgError> {
+    render_template(template, |name| PLACEHOLDERS.contains(&name).then_some(None)).map(|_| ())
+}
+
+/// Replace `{placeholder}`s in a template. Placeholders with no value for
+/// this episode become empty; an empty result leaves the tag unset.
+fn render_template(
+    template: &str,
+    values: impl Fn(&str) -> Option<Option<String>>,
+) -> Result<Option<String>, PgError> {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    let placeholder = PLACEHOLDER.get_or_init(|| Regex::new(r"\{(\w+)\}").unwrap());
+
+    let mut unknown = None;
+    let rendered = placeholder.replace_all(template, |caps: &Captures| match values(&caps[1]) {
+        Some(value) => value.unwrap_or_default(),
+        None => {
+            unknown = Some(caps[1].to_string());
+            String::new()
         }
-        Err(_) => {
-            // audio-metadata not found, log warning and skip
-            tracing::warn!(
-                "audio-metadata not found in PATH. Skipping metadata tagging for {}",
-                file_path.display()
+    });
+    if let Some(name) = unknown {
+        return Err(PgError::Config(format!(
+            "Unknown placeholder '{{{}}}' in tag template '{}'",
+            name, template
+        )));
+    }
+
+    let rendered = rendered.trim();
+    Ok((!rendered.is_empty()).then(|| rendered.to_string()))
+}
+
+/// Reduce HTML, such as an episode description, to a line of plain text
+pub fn plain_text(html: &str) -> String {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    let tag = TAG.get_or_init(|| Regex::new(r"<[^>]*>").unwrap());
+
+    let text = tag
+        .replace_all(html, " ")
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Cover art loaded for embedding
+struct Picture {
+    mime_type: &'static str,
+    data: Vec<u8>,
+}
+
+/// Tag an audio file with the configured backend
+pub fn tag_audio_file(backend: TaggerBackend, file_path: &Path, tags: &Tags) -> Result<(), PgError> {
+    match backend {
+        TaggerBackend::Native => tag_native(file_path, tags),
+        TaggerBackend::AudioMetadata => run_audio_metadata(file_path, tags),
+        TaggerBackend::None => Ok(()),
+    }
+}
+
+/// Tag a file in-process, picking the tag format from its extension
+fn tag_native(file_path: &Path, tags: &Tags) -> Result<(), PgError> {
+    let extension = file_path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let picture = match &tags.cover_art {
+        Some(path) => Some(load_picture(path)?),
+        None => None,
+    };
+
+    match extension.as_str() {
+        "mp3" => write_id3(file_path, tags, picture),
+        "m4a" | "m4b" | "mp4" | "m4v" | "mov" => mp4::write_tags(file_path, tags, picture.as_ref()),
+        "ogg" | "oga" | "opus" => vorbis::write_tags(file_path, tags, picture.as_ref()),
+        _ => {
+            tracing::debug!("No native tagger for {}, leaving it untagged", file_path.display());
+            return Ok(());
+        }
+    }?;
+
+    tracing::debug!(
+        "Tagged {}{}",
+        file_path.display(),
+        if tags.cover_art.is_some() { " with cover art" } else { "" }
+    );
+    Ok(())
+}
+
+fn load_picture(path: &Path) -> Result<Picture, PgError> {
+    let data = std::fs::read(path).map_err(|e| PgError::filesystem("read cover art", path, e))?;
+    let mime_type = image_type(&data).unwrap_or("image/png");
+    Ok(Picture { mime_type, data })
+}
+
+/// MIME type of a JPEG or PNG image, the formats every tag format can embed
+fn image_type(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0xFF, 0xD8, ..] => Some("image/jpeg"),
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        _ => None,
+    }
+}
+
+/// Write an ID3v2.4 tag, keeping any frames we don't set
+/// Apple's `WFED` podcast URL frame. Despite its `W`, iTunes and other
+/// taggers read it as a text frame, with an encoding byte before the text,
+/// which the id3 crate only writes for IDs starting with `T`.
+fn wfed_frame(url: &str) -> id3::Frame {
+    // 3 is ID3v2.4's UTF-8 encoding byte
+    let mut data = vec![3];
+    data.extend_from_slice(url.as_bytes());
+    id3::Frame::with_content(
+        "WFED",
+        id3::Content::Unknown(id3::frame::Unknown {
+            data,
+            version: id3::Version::Id3v24,
+        }),
+    )
+}
+
+fn write_id3(file_path: &Path, tags: &Tags, picture: Option<Picture>) -> Result<(), PgError> {
+    let tagging_error = |e: id3::Error| PgError::Tagging {
+        path: file_path.to_path_buf(),
+        message: e.to_string(),
+    };
+
+    let mut tag = id3::Tag::read_from_path(file_path)
+        .map(Some)
+        .or_else(|e| match e.kind {
+            id3::ErrorKind::NoTag => Ok(None),
+            _ => Err(e),
+        })
+        .map_err(tagging_error)?
+        .unwrap_or_default();
+
+    if let Some(title) = &tags.title {
+        tag.set_title(title);
+    }
+    if let Some(album) = &tags.album {
+        tag.set_album(album);
+    }
+    if let Some(artist) = &tags.artist {
+        tag.set_artist(artist);
+    }
+    if let Some(album_artist) = &tags.album_artist {
+        tag.set_album_artist(album_artist);
+    }
+    if let Some(date) = &tags.date {
+        match date.parse::<id3::Timestamp>() {
+            Ok(timestamp) => {
+                tag.set_date_released(timestamp);
+                tag.set_date_recorded(timestamp);
+            }
+            Err(_) => tracing::debug!("Not writing date '{}', it isn't YYYY-MM-DD", date),
+        }
+    }
+    if let Some(track) = tags.track {
+        tag.set_track(track);
+    }
+    if let Some(disc) = tags.disc {
+        tag.set_disc(disc);
+    }
+    if let Some(genre) = &tags.genre {
+        tag.set_genre(genre);
+    }
+    if let Some(comment) = &tags.comment {
+        tag.remove_comment(Some(""), None);
+        tag.add_frame(id3::frame::Comment {
+            lang: "eng".to_string(),
+            description: String::new(),
+            text: comment.clone(),
+        });
+    }
+    if let Some(lyrics) = &tags.lyrics {
+        tag.remove_all_lyrics();
+        tag.add_frame(id3::frame::Lyrics {
+            lang: "eng".to_string(),
+            description: String::new(),
+            text: lyrics.clone(),
+        });
+    }
+    // Apple's podcast frames for the feed URL and episode GUID
+    if let Some(url) = &tags.podcast_url {
+        tag.remove("WFED");
+        tag.add_frame(wfed_frame(url));
+    }
+    if let Some(id) = &tags.episode_id {
+        tag.add_frame(id3::Frame::text("TGID", id));
+    }
+    if let Some(replaygain) = &tags.replaygain {
+        for (description, value) in [
+            ("REPLAYGAIN_TRACK_GAIN", replaygain.gain_text()),
+            ("REPLAYGAIN_TRACK_PEAK", replaygain.peak_text()),
+        ] {
+            // Other taggers may have written these in lowercase
+            let existing: Vec<String> = tag
+                .extended_texts()
+                .filter(|frame| frame.description.eq_ignore_ascii_case(description))
+                .map(|frame| frame.description.clone())
+                .collect();
+            for existing in existing {
+                tag.remove_extended_text(Some(&existing), None);
+            }
+            tag.add_frame(id3::frame::ExtendedText {
+                description: description.to_string(),
+                value,
+            });
+        }
+    }
+    if let Some(picture) = picture {
+        tag.remove_all_pictures();
+        tag.add_frame(id3::frame::Picture {
+            mime_type: picture.mime_type.to_string(),
+            picture_type: id3::frame::PictureType::CoverFront,
+            description: String::new(),
+            data: picture.data,
+        });
+    }
+    if !tags.chapters.is_empty() {
+        add_id3_chapters(&mut tag, &tags.chapters);
+    }
+
+    tag.write_to_path(file_path, id3::Version::Id3v24)
+        .map_err(tagging_error)
+}
+
+/// Replace a tag's chapters with `CHAP` frames, and a `CTOC` frame listing
+/// the ones that belong in the table of contents
+fn add_id3_chapters(tag: &mut id3::Tag, chapters: &[Chapter]) {
+    tag.remove_all_chapters();
+    tag.remove_all_tables_of_contents();
+
+    let mut toc = Vec::new();
+    for (i, chapter) in chapters.iter().enumerate() {
+        let element_id = format!("chp{}", i);
+        let mut frames = Vec::new();
+        if let Some(title) = &chapter.title {
+            frames.push(id3::Frame::text("TIT2", title));
+        }
+        if let Some(url) = &chapter.url {
+            frames.push(
+                id3::frame::ExtendedLink {
+                    description: String::new(),
+                    link: url.clone(),
+                }
+                .into(),
             );
-            Ok(())
         }
+        if let Some(data) = &chapter.image {
+            match image_type(data) {
+                Some(mime_type) => frames.push(
+                    id3::frame::Picture {
+                        mime_type: mime_type.to_string(),
+                        picture_type: id3::frame::PictureType::Other,
+                        description: String::new(),
+                        data: data.clone(),
+                    }
+                    .into(),
+                ),
+                None => tracing::debug!("Not embedding image for chapter {}, it isn't JPEG or PNG", i),
+            }
+        }
+
+        if chapter.toc {
+            toc.push(element_id.clone());
+        }
+        tag.add_frame(id3::frame::Chapter {
+            element_id,
+            start_time: chapter.start_ms,
+            end_time: chapter.end_ms,
+            // Times are used instead of byte offsets
+            start_offset: u32::MAX,
+            end_offset: u32::MAX,
+            frames,
+        });
     }
+
+    tag.add_frame(id3::frame::TableOfContents {
+        element_id: "toc".to_string(),
+        top_level: true,
+        ordered: true,
+        elements: toc,
+        frames: Vec::new(),
+    });
 }
 
-fn run_audio_metadata(
-    file_path: &Path,
-    podcast_name: &str,
-    episode_title: &str,
-    cover_art_path: Option<&Path>,
-) -> Result<()> {
+/// Tag a file by running the external `audio-metadata` binary, which only
+/// takes the title, album, artist and cover art
+fn run_audio_metadata(file_path: &Path, tags: &Tags) -> Result<(), PgError> {
     let mut cmd = Command::new("audio-metadata");
-    cmd.arg("set")
-        .arg("-f")
-        .arg(file_path)
-        .arg("--album")
-        .arg(podcast_name)
-        .arg("--artist")
-        .arg(podcast_name)
-        .arg("--title")
-        .arg(episode_title);
+    cmd.arg("set").arg("-f").arg(file_path);
+    if let Some(album) = &tags.album {
+        cmd.arg("--album").arg(album);
+    }
+    if let Some(artist) = &tags.artist {
+        cmd.arg("--artist").arg(artist);
+    }
+    if let Some(title) = &tags.title {
+        cmd.arg("--title").arg(title);
+    }
 
     // Add cover art if provided
-    if let Some(cover_path) = cover_art_path {
+    if let Some(cover_path) = &tags.cover_art {
         cmd.arg("-c").arg(cover_path);
     }
 
-    let output = cmd.output().context("Failed to run audio-metadata")?;
+    let output = match cmd.output() {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            tracing::warn!(
+                "audio-metadata not found in PATH. Skipping metadata tagging for {}",
+                file_path.display()
+            );
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(PgError::Tagging {
+                path: file_path.to_path_buf(),
+                message: format!("failed to run audio-metadata: {}", e),
+            })
+        }
+    };
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        tracing::warn!(
-            "audio-metadata failed for {}: {}",
-            file_path.display(),
-            stderr
-        );
-    } else {
-        let cover_info = if cover_art_path.is_some() {
-            " with cover art"
-        } else {
-            ""
-        };
-        tracing::debug!(
-            "Tagged {} with podcast '{}' and episode '{}'{}",
-            file_path.display(),
-            podcast_name,
-            episode_title,
-            cover_info
-        );
+        return Err(PgError::Tagging {
+            path: file_path.to_path_buf(),
+            message: format!("audio-metadata failed: {}", stderr.trim()),
+        });
     }
 
+    tracing::debug!(
+        "Tagged {} with audio-metadata{}",
+        file_path.display(),
+        if tags.cover_art.is_some() { " with cover art" } else { "" }
+    );
     Ok(())
 }
+
+/// Replace a file's contents via a temporary file, so a failed write can't
+/// leave a half-tagged episode behind
+fn replace_file(file_path: &Path, contents: &[u8]) -> Result<(), PgError> {
+    let mut temp_name = file_path.as_os_str().to_owned();
+    temp_name.push(".tagging");
+    let temp_path = std::path::PathBuf::from(temp_name);
+
+    std::fs::write(&temp_path, contents)
+        .map_err(|e| PgError::filesystem("write", &temp_path, e))?;
+    std::fs::rename(&temp_path, file_path).map_err(|e| {
+        let _ = std::fs::remove_file(&temp_path);
+        PgError::filesystem("replace", file_path, e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use id3::TagLike;
+
+    #[test]
+    fn wfed_is_written_as_a_text_frame() {
+        let url = "https://example.com/feed.xml";
+        let mut tag = id3::Tag::new();
+        tag.add_frame(wfed_frame(url));
+        let mut bytes = Vec::new();
+        tag.write_to(&mut bytes, id3::Version::Id3v24).unwrap();
+
+        // Frame ID, 4-byte size, 2 flag bytes, then the encoding byte and text
+        let start = bytes.windows(4).position(|w| w == b"WFED").unwrap();
+        let frame = &bytes[start + 10..];
+        assert_eq!(frame[0], 3);
+        assert_eq!(&frame[1..=url.len()], url.as_bytes());
+        assert_eq!(bytes[start + 7] as usize, url.len() + 1);
+    }
+}
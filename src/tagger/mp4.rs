@@ -0,0 +1,496 @@
+//! iTunes-style metadata for MP4 files (M4A, M4B, MP4), stored as items in
+//! `moov/udta/meta/ilst`, and Nero chapters in `moov/udta/chpl`
+
+use std::path::Path;
+
+use super::{replace_file, Picture, Tags};
+use crate::chapters::Chapter;
+use crate::error::PgError;
+
+/// `data` atom type for binary values such as track numbers
+const TYPE_IMPLICIT: u32 = 0;
+/// `data` atom type for UTF-8 text
+const TYPE_UTF8: u32 = 1;
+const TYPE_JPEG: u32 = 13;
+const TYPE_PNG: u32 = 14;
+
+/// Position of an atom within the buffer it was parsed from
+struct Atom {
+    kind: [u8; 4],
+    start: usize,
+    header_len: usize,
+    end: usize,
+}
+
+impl Atom {
+    fn bytes<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.start..self.end]
+    }
+
+    fn payload<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.start + self.header_len..self.end]
+    }
+}
+
+/// Write tags to an MP4 file, keeping any items we don't set
+pub fn write_tags(file_path: &Path, tags: &Tags, picture: Option<&Picture>) -> Result<(), PgError> {
+    let data = std::fs::read(file_path).map_err(|e| PgError::filesystem("read", file_path, e))?;
+    let tagged = retag(&data, tags, picture).map_err(|message| PgError::Tagging {
+        path: file_path.to_path_buf(),
+        message,
+    })?;
+    replace_file(file_path, &tagged)
+}
+
+/// Rebuild the `moov` atom with new metadata. If media data follows it, the
+/// chunk offsets are moved by however much `moov` grew or shrank.
+fn retag(data: &[u8], tags: &Tags, picture: Option<&Picture>) -> Result<Vec<u8>, String> {
+    let top = parse_atoms(data)?;
+    let moov = top
+        .iter()
+        .find(|atom| &atom.kind == b"moov")
+        .ok_or("no 'moov' atom, not an MP4 file")?;
+    // Fragments carry their own offsets, relative to each `moof`, which
+    // aren't rewritten here
+    let fragmented = top.iter().any(|atom| &atom.kind == b"moof")
+        || parse_atoms(moov.payload(data))?.iter().any(|atom| &atom.kind == b"mvex");
+    if fragmented {
+        return Err("fragmented MP4 files aren't supported".to_string());
+    }
+
+    let mut new_moov = rebuild_moov(moov.payload(data), tags, picture)?;
+    let delta = new_moov.len() as i64 - (moov.end - moov.start) as i64;
+    if delta != 0 {
+        shift_chunk_offsets(&mut new_moov[8..], moov.start as u64, delta)?;
+    }
+
+    let mut out = Vec::with_capacity(data.len() + new_moov.len());
+    out.extend_from_slice(&data[..moov.start]);
+    out.extend_from_slice(&new_moov);
+    out.extend_from_slice(&data[moov.end..]);
+    Ok(out)
+}
+
+fn rebuild_moov(payload: &[u8], tags: &Tags, picture: Option<&Picture>) -> Result<Vec<u8>, String> {
+    let mut children = Vec::new();
+    let mut udta = None;
+    for child in parse_atoms(payload)? {
+        if &child.kind == b"udta" {
+            udta = Some(child.payload(payload));
+        } else {
+            children.extend_from_slice(child.bytes(payload));
+        }
+    }
+    children.extend(rebuild_udta(udta.unwrap_or_default(), tags, picture)?);
+    Ok(atom(b"moov", &children))
+}
+
+fn rebuild_udta(payload: &[u8], tags: &Tags, picture: Option<&Picture>) -> Result<Vec<u8>, String> {
+    let mut children = Vec::new();
+    let mut meta = None;
+    for child in parse_atoms(payload)? {
+        match &child.kind {
+            b"meta" => meta = Some(child.payload(payload)),
+            b"chpl" if !tags.chapters.is_empty() => {}
+            _ => children.extend_from_slice(child.bytes(payload)),
+        }
+    }
+    children.extend(rebuild_meta(meta, tags, picture)?);
+    if !tags.chapters.is_empty() {
+        children.extend(chapter_list(&tags.chapters));
+    }
+    Ok(atom(b"udta", &children))
+}
+
+/// A Nero `chpl` chapter list. It only holds start times and titles, and at
+/// most 255 chapters, so hidden chapters are left out.
+fn chapter_list(chapters: &[Chapter]) -> Vec<u8> {
+    let mut listed: Vec<&Chapter> = chapters.iter().filter(|chapter| chapter.toc).collect();
+    if listed.len() > 255 {
+        tracing::warn!("Only the first 255 of {} chapters fit in an MP4 chapter list", listed.len());
+        listed.truncate(255);
+    }
+
+    // Version 1, no flags, then a reserved field
+    let mut payload = vec![1, 0, 0, 0, 0, 0, 0, 0];
+    payload.push(listed.len() as u8);
+    for chapter in listed {
+        // Start time in 100-nanosecond units
+        payload.extend_from_slice(&(chapter.start_ms as u64 * 10_000).to_be_bytes());
+        // Titles are length-prefixed with a byte, so long ones are cut short
+        let title = chapter.title.as_deref().unwrap_or_default();
+        let mut len = title.len().min(255);
+        while !title.is_char_boundary(len) {
+            len -= 1;
+        }
+        if len < title.len() {
+            tracing::warn!("Chapter title cut to {} bytes in the MP4 chapter list: '{}'", len, &title[..len]);
+        }
+        payload.push(len as u8);
+        payload.extend_from_slice(&title.as_bytes()[..len]);
+    }
+    atom(b"chpl", &payload)
+}
+
+fn rebuild_meta(payload: Option<&[u8]>, tags: &Tags, picture: Option<&Picture>) -> Result<Vec<u8>, String> {
+    // ISO `meta` is a full atom with version and flags before its children;
+    // QuickTime's has none
+    let (header, children_data) = match payload {
+        Some(payload) if payload.len() >= 8 && &payload[4..8] == b"hdlr" => (&[][..], payload),
+        Some(payload) if payload.len() >= 4 => payload.split_at(4),
+        _ => (&[0u8; 4][..], &[][..]),
+    };
+
+    let mut children = header.to_vec();
+    let mut has_handler = false;
+    let mut ilst = None;
+    for child in parse_atoms(children_data)? {
+        match &child.kind {
+            b"ilst" => ilst = Some(child.payload(children_data)),
+            b"hdlr" => {
+                has_handler = true;
+                children.extend_from_slice(child.bytes(children_data));
+            }
+            _ => children.extend_from_slice(child.bytes(children_data)),
+        }
+    }
+    if !has_handler {
+        let mut handler = vec![0u8; 8];
+        handler.extend_from_slice(b"mdirappl");
+        handler.extend_from_slice(&[0u8; 9]);
+        children.splice(header.len()..header.len(), atom(b"hdlr", &handler));
+    }
+    children.extend(rebuild_ilst(ilst.unwrap_or_default(), tags, picture)?);
+    Ok(atom(b"meta", &children))
+}
+
+fn rebuild_ilst(payload: &[u8], tags: &Tags, picture: Option<&Picture>) -> Result<Vec<u8>, String> {
+    let text_items: [(&[u8; 4], &Option<String>); 10] = [
+        (b"\xA9nam", &tags.title),
+        (b"\xA9alb", &tags.album),
+        (b"\xA9ART", &tags.artist),
+        (b"aART", &tags.album_artist),
+        (b"\xA9day", &tags.date),
+        (b"\xA9gen", &tags.genre),
+        (b"\xA9cmt", &tags.comment),
+        (b"\xA9lyr", &tags.lyrics),
+        (b"purl", &tags.podcast_url),
+        (b"egid", &tags.episode_id),
+    ];
+    let mut items: Vec<Vec<u8>> = text_items
+        .into_iter()
+        .filter_map(|(kind, value)| value.as_deref().map(|value| item(kind, TYPE_UTF8, value.as_bytes())))
+        .collect();
+    // Track and disc are numbers followed by a total, which we leave at 0
+    if let Some(track) = tags.track {
+        let mut value = vec![0u8; 8];
+        value[2..4].copy_from_slice(&(track.min(u16::MAX as u32) as u16).to_be_bytes());
+        items.push(item(b"trkn", TYPE_IMPLICIT, &value));
+    }
+    if let Some(disc) = tags.disc {
+        let mut value = vec![0u8; 6];
+        value[2..4].copy_from_slice(&(disc.min(u16::MAX as u32) as u16).to_be_bytes());
+        items.push(item(b"disk", TYPE_IMPLICIT, &value));
+    }
+    if let Some(picture) = picture {
+        let data_type = if picture.mime_type == "image/jpeg" { TYPE_JPEG } else { TYPE_PNG };
+        items.push(item(b"covr", data_type, &picture.data));
+    }
+    let mut freeform_names = Vec::new();
+    if let Some(replaygain) = &tags.replaygain {
+        for (name, value) in [
+            ("replaygain_track_gain", replaygain.gain_text()),
+            ("replaygain_track_peak", replaygain.peak_text()),
+        ] {
+            items.push(freeform_item(name, &value));
+            freeform_names.push(name);
+        }
+    }
+
+    let mut children = Vec::new();
+    for existing in parse_atoms(payload)? {
+        // Freeform items all share a type, so they are told apart by name
+        let replaced = if &existing.kind == b"----" {
+            freeform_name(existing.payload(payload))
+                .is_some_and(|name| freeform_names.iter().any(|ours| ours.eq_ignore_ascii_case(&name)))
+        } else {
+            items.iter().any(|item| item[4..8] == existing.kind)
+        };
+        if !replaced {
+            children.extend_from_slice(existing.bytes(payload));
+        }
+    }
+    children.extend(items.concat());
+    Ok(atom(b"ilst", &children))
+}
+
+fn item(kind: &[u8; 4], data_type: u32, value: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(value.len() + 8);
+    data.extend_from_slice(&data_type.to_be_bytes());
+    data.extend_from_slice(&[0u8; 4]);
+    data.extend_from_slice(value);
+    atom(kind, &atom(b"data", &data))
+}
+
+/// A freeform `----` item, named in iTunes' namespace as other taggers do
+fn freeform_item(name: &str, value: &str) -> Vec<u8> {
+    let mut mean = vec![0u8; 4];
+    mean.extend_from_slice(b"com.apple.iTunes");
+    let mut name_payload = vec![0u8; 4];
+    name_payload.extend_from_slice(name.as_bytes());
+    let mut data = Vec::with_capacity(value.len() + 8);
+    data.extend_from_slice(&TYPE_UTF8.to_be_bytes());
+    data.extend_from_slice(&[0u8; 4]);
+    data.extend_from_slice(value.as_bytes());
+
+    let children = [atom(b"mean", &mean), atom(b"name", &name_payload), atom(b"data", &data)].concat();
+    atom(b"----", &children)
+}
+
+/// The name of a freeform item, from its `name` atom
+fn freeform_name(payload: &[u8]) -> Option<String> {
+    let atoms = parse_atoms(payload).ok()?;
+    let name = atoms.iter().find(|atom| &atom.kind == b"name")?.payload(payload);
+    Some(String::from_utf8_lossy(name.get(4..)?).into_owned())
+}
+
+fn atom(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 8);
+    out.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(payload);
+    out
+}
+
+/// Split a buffer into the atoms it contains
+fn parse_atoms(data: &[u8]) -> Result<Vec<Atom>, String> {
+    let mut atoms = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as u64;
+        let kind: [u8; 4] = data[pos + 4..pos + 8].try_into().unwrap();
+        let (size, header_len) = match size {
+            // Extends to the end of the file
+            0 => ((data.len() - pos) as u64, 8),
+            // 64-bit size follows the type
+            1 if pos + 16 <= data.len() => {
+                (u64::from_be_bytes(data[pos + 8..pos + 16].try_into().unwrap()), 16)
+            }
+            size => (size, 8),
+        };
+        if size < header_len as u64 || size > (data.len() - pos) as u64 {
+            return Err(format!(
+                "invalid size for '{}' atom",
+                String::from_utf8_lossy(&kind)
+            ));
+        }
+        let end = pos + size as usize;
+        atoms.push(Atom {
+            kind,
+            start: pos,
+            header_len,
+            end,
+        });
+        pos = end;
+    }
+    Ok(atoms)
+}
+
+/// Move chunk offsets pointing past `moved_from` by `delta` bytes, in every
+/// track's `stco` or `co64` table
+fn shift_chunk_offsets(payload: &mut [u8], moved_from: u64, delta: i64) -> Result<(), String> {
+    for child in parse_atoms(payload)? {
+        let body = &mut payload[child.start + child.header_len..child.end];
+        match &child.kind {
+            b"trak" | b"mdia" | b"minf" | b"stbl" => shift_chunk_offsets(body, moved_from, delta)?,
+            b"stco" => shift_offset_table(body, 4, moved_from, delta)?,
+            b"co64" => shift_offset_table(body, 8, moved_from, delta)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn shift_offset_table(body: &mut [u8], width: usize, moved_from: u64, delta: i64) -> Result<(), String> {
+    let count = body
+        .get(4..8)
+        .map(|count| u32::from_be_bytes(count.try_into().unwrap()) as usize)
+        .ok_or("truncated chunk offset table")?;
+    if body.len() < 8 + count * width {
+        return Err("truncated chunk offset table".to_string());
+    }
+
+    for entry in body[8..8 + count * width].chunks_exact_mut(width) {
+        let offset = match width {
+            4 => u32::from_be_bytes(entry.try_into().unwrap()) as u64,
+            _ => u64::from_be_bytes(entry.try_into().unwrap()),
+        };
+        if offset < moved_from {
+            continue;
+        }
+        let shifted = offset
+            .checked_add_signed(delta)
+            .ok_or("chunk offset out of range")?;
+        match width {
+            4 => {
+                let shifted = u32::try_from(shifted).map_err(|_| "chunk offset out of range")?;
+                entry.copy_from_slice(&shifted.to_be_bytes());
+            }
+            _ => entry.copy_from_slice(&shifted.to_be_bytes()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUDIO: &[u8] = b"audio frames that must not move or change";
+
+    /// A minimal MP4 with one track whose two chunks point into `mdat`,
+    /// with `moov` before or after the media data
+    fn build(moov_first: bool, udta: Option<Vec<u8>>) -> Vec<u8> {
+        let ftyp = atom(b"ftyp", b"M4A \0\0\0\0M4A isom");
+        let mdat = atom(b"mdat", AUDIO);
+        if moov_first {
+            let moov_len = build_moov(&[0, 0], udta.clone()).len();
+            let start = (ftyp.len() + moov_len + 8) as u32;
+            [ftyp, build_moov(&[start, start + 10], udta), mdat].concat()
+        } else {
+            let start = (ftyp.len() + 8) as u32;
+            [ftyp, mdat, build_moov(&[start, start + 10], udta)].concat()
+        }
+    }
+
+    fn build_moov(offsets: &[u32], udta: Option<Vec<u8>>) -> Vec<u8> {
+        let mut stco = vec![0u8; 4];
+        stco.extend_from_slice(&(offsets.len() as u32).to_be_bytes());
+        for offset in offsets {
+            stco.extend_from_slice(&offset.to_be_bytes());
+        }
+        let stbl = atom(b"stbl", &atom(b"stco", &stco));
+        let trak = atom(b"trak", &atom(b"mdia", &atom(b"minf", &stbl)));
+        let mut children = [atom(b"mvhd", &[0u8; 100]), trak].concat();
+        if let Some(udta) = udta {
+            children.extend(udta);
+        }
+        atom(b"moov", &children)
+    }
+
+    /// Find an atom by its path from the top level
+    fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> &'a [u8] {
+        let (first, rest) = path.split_first().unwrap();
+        let atoms = parse_atoms(data).unwrap();
+        let atom = atoms.iter().find(|atom| &atom.kind == *first).unwrap();
+        let payload = atom.payload(data);
+        if rest.is_empty() {
+            payload
+        } else if *first == b"meta" {
+            find(&payload[4..], rest)
+        } else {
+            find(payload, rest)
+        }
+    }
+
+    fn chunk_offsets(data: &[u8]) -> Vec<usize> {
+        let stco = find(data, &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stco"]);
+        stco[8..]
+            .chunks_exact(4)
+            .map(|entry| u32::from_be_bytes(entry.try_into().unwrap()) as usize)
+            .collect()
+    }
+
+    fn tags() -> Tags {
+        Tags {
+            title: Some("Episode \u{e9}".to_string()),
+            track: Some(7),
+            ..Default::default()
+        }
+    }
+
+    fn assert_round_trip(original: &[u8]) {
+        let tagged = retag(original, &tags(), None).unwrap();
+
+        assert_eq!(find(&tagged, &[b"mdat"]), AUDIO);
+        let before = chunk_offsets(original);
+        let after = chunk_offsets(&tagged);
+        for (before, after) in before.iter().zip(&after) {
+            assert_eq!(original[*before..*before + 10], tagged[*after..*after + 10]);
+        }
+
+        let title = find(&tagged, &[b"moov", b"udta", b"meta", b"ilst", b"\xA9nam", b"data"]);
+        assert_eq!(&title[8..], "Episode \u{e9}".as_bytes());
+        let track = find(&tagged, &[b"moov", b"udta", b"meta", b"ilst", b"trkn", b"data"]);
+        assert_eq!(&track[8..], &[0, 0, 0, 7, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn retag_shifts_offsets_when_moov_comes_first() {
+        let original = build(true, None);
+        assert_eq!(&original[chunk_offsets(&original)[0]..][..10], &AUDIO[..10]);
+        assert_round_trip(&original);
+    }
+
+    #[test]
+    fn retag_leaves_offsets_when_moov_comes_last() {
+        let original = build(false, None);
+        let tagged = retag(&original, &tags(), None).unwrap();
+        assert_eq!(chunk_offsets(&tagged), chunk_offsets(&original));
+        assert_round_trip(&original);
+    }
+
+    #[test]
+    fn retag_keeps_items_it_does_not_set() {
+        let ilst = [
+            item(b"\xA9nam", TYPE_UTF8, b"Old title"),
+            item(b"\xA9too", TYPE_UTF8, b"Encoder"),
+        ]
+        .concat();
+        let meta = [vec![0u8; 4], atom(b"ilst", &ilst)].concat();
+        let udta = atom(b"udta", &atom(b"meta", &meta));
+        let original = build(true, Some(udta));
+        assert_round_trip(&original);
+
+        let tagged = retag(&original, &tags(), None).unwrap();
+        let tool = find(&tagged, &[b"moov", b"udta", b"meta", b"ilst", b"\xA9too", b"data"]);
+        assert_eq!(&tool[8..], b"Encoder");
+        // The old title was replaced, not added to
+        let ilst = find(&tagged, &[b"moov", b"udta", b"meta", b"ilst"]);
+        let titles = parse_atoms(ilst).unwrap().iter().filter(|atom| &atom.kind == b"\xA9nam").count();
+        assert_eq!(titles, 1);
+    }
+
+    #[test]
+    fn chapter_list_truncates_titles_and_count() {
+        let chapter = |start_ms: u32, title: String| Chapter {
+            start_ms,
+            end_ms: start_ms + 1000,
+            title: Some(title),
+            url: None,
+            image: None,
+            toc: true,
+        };
+        // 'é' is two bytes, so a 255-byte cut would split the last one
+        let mut chapters = vec![chapter(0, "\u{e9}".repeat(200))];
+        chapters.extend((1..300).map(|i| chapter(i * 1000, format!("Chapter {}", i))));
+
+        let chpl = chapter_list(&chapters);
+        let payload = &chpl[8..];
+        assert_eq!(payload[8], 255);
+        let title_len = payload[17] as usize;
+        assert_eq!(title_len, 254);
+        assert!(std::str::from_utf8(&payload[18..18 + title_len]).is_ok());
+    }
+
+    #[test]
+    fn retag_refuses_fragmented_files() {
+        let mut original = build(false, None);
+        original.extend(atom(b"moof", &atom(b"mfhd", &[0u8; 8])));
+        assert!(retag(&original, &tags(), None).is_err());
+
+        let mvex = atom(b"mvex", &atom(b"trex", &[0u8; 24]));
+        let original = build(true, Some(mvex));
+        assert!(retag(&original, &tags(), None).is_err());
+    }
+}
@@ -0,0 +1,304 @@
+//! Vorbis comments for Ogg Vorbis and Opus files
+
+use std::io::Cursor;
+use std::path::Path;
+
+use base64::Engine;
+use ogg::{PacketReader, PacketWriteEndInfo, PacketWriter};
+
+use super::{replace_file, Picture, Tags};
+use crate::error::PgError;
+
+/// Comment header magic for each codec
+const VORBIS_COMMENT: &[u8] = b"\x03vorbis";
+const OPUS_TAGS: &[u8] = b"OpusTags";
+
+/// FLAC picture type for the front cover
+const COVER_FRONT: u32 = 3;
+
+/// Write tags to an Ogg file, keeping any comments we don't set
+pub fn write_tags(file_path: &Path, tags: &Tags, picture: Option<&Picture>) -> Result<(), PgError> {
+    let data = std::fs::read(file_path).map_err(|e| PgError::filesystem("read", file_path, e))?;
+    let tagged = retag(&data, tags, picture).map_err(|message| PgError::Tagging {
+        path: file_path.to_path_buf(),
+        message,
+    })?;
+    replace_file(file_path, &tagged)
+}
+
+/// Copy the Ogg stream packet by packet, swapping in a new comment header.
+/// Page boundaries are kept, since both codecs need the headers to end
+/// their pages before audio starts.
+fn retag(data: &[u8], tags: &Tags, picture: Option<&Picture>) -> Result<Vec<u8>, String> {
+    let mut reader = PacketReader::new(Cursor::new(data));
+    let mut writer = PacketWriter::new(Vec::with_capacity(data.len() + 1024));
+
+    let mut serial = None;
+    let mut magic = None;
+    let mut replaced = false;
+
+    while let Some(packet) = reader.read_packet().map_err(|e| e.to_string())? {
+        let end_info = if packet.last_in_stream() {
+            PacketWriteEndInfo::EndStream
+        } else if packet.last_in_page() {
+            PacketWriteEndInfo::EndPage
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+
+        let mut contents = packet.data.clone();
+        let stream = packet.stream_serial();
+        match serial {
+            None => {
+                // The identification header tells us the codec
+                magic = if packet.data.starts_with(b"\x01vorbis") {
+                    Some(VORBIS_COMMENT)
+                } else if packet.data.starts_with(b"OpusHead") {
+                    Some(OPUS_TAGS)
+                } else {
+                    return Err("not an Ogg Vorbis or Opus file".to_string());
+                };
+                serial = Some(stream);
+            }
+            Some(first) if first == stream && !replaced => {
+                let magic = magic.unwrap_or(VORBIS_COMMENT);
+                if !packet.data.starts_with(magic) {
+                    return Err("missing comment header".to_string());
+                }
+                contents = comment_header(&packet.data, magic, tags, picture)?;
+                replaced = true;
+            }
+            _ => {}
+        }
+
+        writer
+            .write_packet(contents.into_boxed_slice(), stream, end_info, packet.absgp_page())
+            .map_err(|e| e.to_string())?;
+    }
+
+    if !replaced {
+        return Err("missing comment header".to_string());
+    }
+    Ok(writer.into_inner())
+}
+
+/// Build a new comment header from an existing one
+fn comment_header(
+    packet: &[u8],
+    magic: &[u8],
+    tags: &Tags,
+    picture: Option<&Picture>,
+) -> Result<Vec<u8>, String> {
+    let (vendor, existing, trailing) = parse_comments(&packet[magic.len()..]).ok_or("invalid comment header")?;
+
+    let track = tags.track.map(|n| n.to_string());
+    let disc = tags.disc.map(|n| n.to_string());
+    let gain = tags.replaygain.map(|replaygain| replaygain.gain_text());
+    let peak = tags.replaygain.map(|replaygain| replaygain.peak_text());
+    let fields: [(&str, Option<&String>); 14] = [
+        ("TITLE", tags.title.as_ref()),
+        ("ALBUM", tags.album.as_ref()),
+        ("ARTIST", tags.artist.as_ref()),
+        ("ALBUMARTIST", tags.album_artist.as_ref()),
+        ("DATE", tags.date.as_ref()),
+        ("TRACKNUMBER", track.as_ref()),
+        ("DISCNUMBER", disc.as_ref()),
+        ("GENRE", tags.genre.as_ref()),
+        ("COMMENT", tags.comment.as_ref()),
+        ("LYRICS", tags.lyrics.as_ref()),
+        // Names other taggers use for ID3's WFED and TGID podcast frames
+        ("PODCASTURL", tags.podcast_url.as_ref()),
+        ("PODCASTID", tags.episode_id.as_ref()),
+        ("REPLAYGAIN_TRACK_GAIN", gain.as_ref()),
+        ("REPLAYGAIN_TRACK_PEAK", peak.as_ref()),
+    ];
+    let mut comments: Vec<String> = fields
+        .into_iter()
+        .filter_map(|(key, value)| value.map(|value| format!("{}={}", key, value)))
+        .collect();
+    if let Some(picture) = picture {
+        let block = base64::engine::general_purpose::STANDARD.encode(picture_block(picture));
+        comments.push(format!("METADATA_BLOCK_PICTURE={}", block));
+    }
+
+    let replaced_keys: Vec<String> = comments
+        .iter()
+        .filter_map(|comment| comment.split_once('=').map(|(key, _)| key.to_string()))
+        .collect();
+    let kept = existing.into_iter().filter(|comment| {
+        let key = comment.split_once('=').map_or(comment.as_str(), |(key, _)| key);
+        !replaced_keys.iter().any(|replaced| replaced.eq_ignore_ascii_case(key))
+    });
+    let comments: Vec<String> = kept.chain(comments).collect();
+
+    let mut out = magic.to_vec();
+    out.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    out.extend_from_slice(vendor.as_bytes());
+    out.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for comment in &comments {
+        out.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        out.extend_from_slice(comment.as_bytes());
+    }
+    if magic == VORBIS_COMMENT && trailing.is_empty() {
+        // Framing bit
+        out.push(1);
+    }
+    // Vorbis' framing bit, or Opus padding and binary data, kept as it was
+    out.extend_from_slice(trailing);
+    Ok(out)
+}
+
+/// Vendor string, comments and whatever follows them in a comment header,
+/// after its magic
+fn parse_comments(data: &[u8]) -> Option<(String, Vec<String>, &[u8])> {
+    let mut pos = 0;
+    let read_string = |pos: &mut usize| -> Option<String> {
+        let len = u32::from_le_bytes(data.get(*pos..*pos + 4)?.try_into().ok()?) as usize;
+        let value = data.get(*pos + 4..*pos + 4 + len)?;
+        *pos += 4 + len;
+        Some(String::from_utf8_lossy(value).into_owned())
+    };
+
+    let vendor = read_string(&mut pos)?;
+    let count = u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?);
+    pos += 4;
+    let comments = (0..count)
+        .map(|_| read_string(&mut pos))
+        .collect::<Option<Vec<_>>>()?;
+    Some((vendor, comments, &data[pos..]))
+}
+
+/// A FLAC picture block, as embedded in `METADATA_BLOCK_PICTURE`
+fn picture_block(picture: &Picture) -> Vec<u8> {
+    let mut block = Vec::with_capacity(picture.data.len() + 64);
+    block.extend_from_slice(&COVER_FRONT.to_be_bytes());
+    block.extend_from_slice(&(picture.mime_type.len() as u32).to_be_bytes());
+    block.extend_from_slice(picture.mime_type.as_bytes());
+    // Description, then width, height, depth and palette size, all unknown
+    block.extend_from_slice(&[0u8; 4 * 5]);
+    block.extend_from_slice(&(picture.data.len() as u32).to_be_bytes());
+    block.extend_from_slice(&picture.data);
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERIAL: u32 = 0x5047;
+
+    fn comment_packet(magic: &[u8], comments: &[&str], trailing: &[u8]) -> Vec<u8> {
+        let mut packet = magic.to_vec();
+        packet.extend_from_slice(&4u32.to_le_bytes());
+        packet.extend_from_slice(b"test");
+        packet.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            packet.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            packet.extend_from_slice(comment.as_bytes());
+        }
+        packet.extend_from_slice(trailing);
+        packet
+    }
+
+    /// An Ogg stream of header packets, each ending its page, then audio
+    /// packets with increasing granule positions
+    fn build(headers: Vec<Vec<u8>>, audio: &[&[u8]]) -> Vec<u8> {
+        let mut writer = PacketWriter::new(Vec::new());
+        for header in headers {
+            writer
+                .write_packet(header.into_boxed_slice(), SERIAL, PacketWriteEndInfo::EndPage, 0)
+                .unwrap();
+        }
+        for (i, packet) in audio.iter().enumerate() {
+            let end_info = if i + 1 == audio.len() {
+                PacketWriteEndInfo::EndStream
+            } else {
+                PacketWriteEndInfo::EndPage
+            };
+            writer
+                .write_packet(packet.to_vec().into_boxed_slice(), SERIAL, end_info, (i as u64 + 1) * 960)
+                .unwrap();
+        }
+        writer.into_inner()
+    }
+
+    /// Each packet's data and granule position
+    fn packets(data: &[u8]) -> Vec<(Vec<u8>, u64)> {
+        let mut reader = PacketReader::new(Cursor::new(data));
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.read_packet().unwrap() {
+            packets.push((packet.data.clone(), packet.absgp_page()));
+        }
+        packets
+    }
+
+    fn tags() -> Tags {
+        Tags {
+            title: Some("New title".to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Check everything but the comment header, the second packet, is unchanged
+    fn assert_audio_unchanged(original: &[u8], tagged: &[u8]) {
+        let before = packets(original);
+        let after = packets(tagged);
+        assert_eq!(before.len(), after.len());
+        for (i, (before, after)) in before.iter().zip(&after).enumerate() {
+            if i != 1 {
+                assert_eq!(before, after);
+            }
+        }
+    }
+
+    #[test]
+    fn opus_round_trip_keeps_binary_data() {
+        let head = b"OpusHead\x01\x01\x38\x01\x80\xbb\0\0\0\0\0".to_vec();
+        let trailing = [1, 0xAA, 0xBB];
+        let comments = comment_packet(OPUS_TAGS, &["TITLE=Old title", "ENCODER=test"], &trailing);
+        let original = build(vec![head, comments], &[b"frame one", b"frame two", b"frame three"]);
+
+        let tagged = retag(&original, &tags(), None).unwrap();
+        assert_audio_unchanged(&original, &tagged);
+
+        let header = &packets(&tagged)[1].0;
+        let (vendor, comments, rest) = parse_comments(&header[OPUS_TAGS.len()..]).unwrap();
+        assert_eq!(vendor, "test");
+        assert_eq!(comments, ["ENCODER=test", "TITLE=New title"]);
+        assert_eq!(rest, trailing);
+    }
+
+    #[test]
+    fn opus_round_trip_keeps_padding() {
+        let head = b"OpusHead\x01\x01\x38\x01\x80\xbb\0\0\0\0\0".to_vec();
+        let comments = comment_packet(OPUS_TAGS, &[], &[0; 16]);
+        let original = build(vec![head, comments], &[b"frame"]);
+
+        let tagged = retag(&original, &tags(), None).unwrap();
+        assert_audio_unchanged(&original, &tagged);
+        let header = &packets(&tagged)[1].0;
+        assert!(header.ends_with(&[0; 16]));
+    }
+
+    #[test]
+    fn vorbis_round_trip_keeps_framing_bit() {
+        let ident = b"\x01vorbis\0\0\0\0\x01\x44\xac\0\0".to_vec();
+        let comments = comment_packet(VORBIS_COMMENT, &["ARTIST=Someone"], &[1]);
+        let setup = b"\x05vorbis setup data".to_vec();
+        let original = build(vec![ident, comments, setup], &[b"frame one", b"frame two"]);
+
+        let tagged = retag(&original, &tags(), None).unwrap();
+        assert_audio_unchanged(&original, &tagged);
+
+        let header = &packets(&tagged)[1].0;
+        let (_, comments, rest) = parse_comments(&header[VORBIS_COMMENT.len()..]).unwrap();
+        assert_eq!(comments, ["ARTIST=Someone", "TITLE=New title"]);
+        assert_eq!(rest, [1]);
+    }
+
+    #[test]
+    fn retag_rejects_other_codecs() {
+        let original = build(vec![b"\x7fFLAC".to_vec()], &[b"frame"]);
+        assert!(retag(&original, &tags(), None).is_err());
+    }
+}
@@ -0,0 +1,484 @@
+//! Episode transcripts, linked from feeds with `podcast:transcript`
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::config::{TranscriptFormat, TranscriptSettings};
+use crate::error::PgError;
+use crate::feed::TranscriptLink;
+use crate::http::HttpClient;
+use crate::tagger::plain_text;
+
+/// The formats feeds publish transcripts in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SourceFormat {
+    Vtt,
+    Srt,
+    /// Podcasting 2.0 JSON transcript
+    Json,
+    Html,
+    Text,
+}
+
+impl SourceFormat {
+    /// Work out a transcript's format from its MIME type, or failing that
+    /// its URL's extension
+    fn detect(link: &TranscriptLink) -> Option<Self> {
+        let by_type = match link.mime_type.as_deref() {
+            Some("text/vtt") => Some(SourceFormat::Vtt),
+            Some("application/x-subrip" | "application/srt" | "application/x-srt" | "text/srt") => {
+                Some(SourceFormat::Srt)
+            }
+            Some("application/json") => Some(SourceFormat::Json),
+            Some("text/html") => Some(SourceFormat::Html),
+            Some("text/plain") => Some(SourceFormat::Text),
+            _ => None,
+        };
+        by_type.or_else(|| {
+            let path = link.url.split(['?', '#']).next().unwrap_or_default();
+            match path.rsplit_once('.')?.1.to_lowercase().as_str() {
+                "vtt" => Some(SourceFormat::Vtt),
+                "srt" => Some(SourceFormat::Srt),
+                "json" => Some(SourceFormat::Json),
+                "html" | "htm" => Some(SourceFormat::Html),
+                "txt" => Some(SourceFormat::Text),
+                _ => None,
+            }
+        })
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            SourceFormat::Vtt => "vtt",
+            SourceFormat::Srt => "srt",
+            SourceFormat::Json => "json",
+            SourceFormat::Html => "html",
+            SourceFormat::Text => "txt",
+        }
+    }
+
+    fn matches(self, format: TranscriptFormat) -> bool {
+        matches!(
+            (self, format),
+            (SourceFormat::Vtt, TranscriptFormat::Vtt)
+                | (SourceFormat::Srt, TranscriptFormat::Srt)
+                | (SourceFormat::Text, TranscriptFormat::Txt)
+        )
+    }
+}
+
+/// One timed piece of a transcript
+struct Cue {
+    start_ms: u64,
+    end_ms: u64,
+    speaker: Option<String>,
+    /// The cue's text, which may span several lines
+    text: String,
+}
+
+/// A parsed transcript. HTML and plain text transcripts have no timings.
+enum Transcript {
+    Timed(Vec<Cue>),
+    Untimed(String),
+}
+
+/// Podcasting 2.0 JSON transcript
+#[derive(Deserialize)]
+struct JsonTranscript {
+    segments: Vec<JsonSegment>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonSegment {
+    #[serde(default)]
+    speaker: Option<String>,
+    start_time: f64,
+    end_time: f64,
+    body: String,
+}
+
+/// A transcript saved next to an episode
+pub struct SavedTranscript {
+    pub path: PathBuf,
+    /// The transcript as plain text, when it should be embedded as lyrics
+    pub lyrics: Option<String>,
+}
+
+/// Download the best of an episode's transcripts and save it next to the
+/// episode with a matching name, converted to the configured format.
+/// Returns `None` if the feed offers no transcript `pg` can read.
+pub async fn fetch_transcript(
+    http: &HttpClient,
+    links: &[TranscriptLink],
+    settings: TranscriptSettings,
+    file_path: &Path,
+) -> Result<Option<SavedTranscript>, PgError> {
+    let candidates = links
+        .iter()
+        .filter_map(|link| SourceFormat::detect(link).map(|format| (link, format)));
+    // The configured format if it's offered, otherwise timed formats first
+    let preference = |format: SourceFormat| {
+        let wanted = settings.format.is_some_and(|target| format.matches(target));
+        let rank = [SourceFormat::Vtt, SourceFormat::Srt, SourceFormat::Json, SourceFormat::Html]
+            .iter()
+            .position(|f| *f == format)
+            .unwrap_or(4);
+        (!wanted, rank)
+    };
+    let Some((link, source)) = candidates.min_by_key(|(_, format)| preference(*format)) else {
+        return Ok(None);
+    };
+
+    let body = http.get_bytes(&link.url).await?;
+    let raw = String::from_utf8_lossy(&body);
+    let raw = raw.strip_prefix('\u{feff}').unwrap_or(&raw);
+
+    let target = settings.format.filter(|target| !source.matches(*target));
+    let transcript = if target.is_some() || settings.lyrics {
+        Some(parse(raw, source)?)
+    } else {
+        None
+    };
+
+    let (extension, contents) = match (target, &transcript) {
+        (Some(target), Some(transcript)) => render(transcript, target),
+        _ => (source.extension(), raw.to_string()),
+    };
+    let path = file_path.with_extension(extension);
+    std::fs::write(&path, contents).map_err(|e| PgError::filesystem("write transcript", &path, e))?;
+
+    let lyrics = transcript
+        .filter(|_| settings.lyrics)
+        .map(|transcript| to_text(&transcript))
+        .filter(|text| !text.is_empty());
+    Ok(Some(SavedTranscript { path, lyrics }))
+}
+
+fn parse(raw: &str, source: SourceFormat) -> Result<Transcript, PgError> {
+    Ok(match source {
+        SourceFormat::Vtt | SourceFormat::Srt => Transcript::Timed(parse_cues(raw)),
+        SourceFormat::Json => {
+            let json: JsonTranscript =
+                serde_json::from_str(raw).map_err(|e| PgError::parse("transcript JSON", e))?;
+            let cues = json
+                .segments
+                .into_iter()
+                .map(|segment| Cue {
+                    start_ms: seconds_to_millis(segment.start_time),
+                    end_ms: seconds_to_millis(segment.end_time),
+                    speaker: segment.speaker.filter(|speaker| !speaker.trim().is_empty()),
+                    text: segment.body.trim().to_string(),
+                })
+                .filter(|cue| !cue.text.is_empty())
+                .collect();
+            Transcript::Timed(cues)
+        }
+        SourceFormat::Html => Transcript::Untimed(html_to_text(raw)),
+        SourceFormat::Text => Transcript::Untimed(raw.trim().to_string()),
+    })
+}
+
+/// Parse SRT or WebVTT cues. Blocks without a timing line, such as the
+/// `WEBVTT` header and `NOTE`s, are skipped.
+fn parse_cues(raw: &str) -> Vec<Cue> {
+    static VOICE: OnceLock<Regex> = OnceLock::new();
+    let voice = VOICE.get_or_init(|| Regex::new(r"^<v(?:\.[^\s>]*)?\s+([^>]+)>").unwrap());
+
+    let raw = raw.replace("\r\n", "\n");
+    let mut cues = Vec::new();
+    for block in raw.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let Some(timing) = lines.next() else {
+            continue;
+        };
+        let Some((start, end)) = timing.split_once("-->") else {
+            continue;
+        };
+        // WebVTT cue settings may follow the end time
+        let end = end.split_whitespace().next().unwrap_or_default();
+        let (Some(start_ms), Some(end_ms)) = (parse_timestamp(start), parse_timestamp(end)) else {
+            continue;
+        };
+
+        let text = lines.collect::<Vec<_>>().join("\n");
+        let speaker = voice.captures(&text).map(|caps| caps[1].trim().to_string());
+        let text = text
+            .lines()
+            .map(cue_text)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        if !text.is_empty() {
+            cues.push(Cue {
+                start_ms,
+                end_ms,
+                speaker,
+                text,
+            });
+        }
+    }
+    cues
+}
+
+/// One line of a cue without its markup: WebVTT's class, voice, language,
+/// ruby and timestamp tags, and the formatting tags found in SRT files
+fn cue_text(line: &str) -> String {
+    static MARKUP: OnceLock<Regex> = OnceLock::new();
+    let markup = MARKUP.get_or_init(|| {
+        Regex::new(r"(?i)</?(?:b|c|i|u|v|lang|ruby|rt|font)(?:[\s.][^>]*)?>|<\d[^>]*>").unwrap()
+    });
+
+    // Tags sit inside words and sentences, so they go without leaving a space
+    let text = markup
+        .replace_all(line, "")
+        .replace("&nbsp;", " ")
+        .replace("&lrm;", "")
+        .replace("&rlm;", "")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Parse `HH:MM:SS,mmm` (SRT) or `[HH:]MM:SS.mmm` (WebVTT) into milliseconds
+fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let (clock, millis) = timestamp.trim().split_once([',', '.'])?;
+    let millis: u64 = format!("{:0<3}", millis).get(..3)?.parse().ok()?;
+    let parts = clock
+        .split(':')
+        .map(|part| part.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let seconds = match parts.as_slice() {
+        [h, m, s] => h * 3600 + m * 60 + s,
+        [m, s] => m * 60 + s,
+        _ => return None,
+    };
+    Some(seconds * 1000 + millis)
+}
+
+/// Plain text from an HTML transcript, a line per paragraph
+fn html_to_text(html: &str) -> String {
+    static BLOCK: OnceLock<Regex> = OnceLock::new();
+    let block = BLOCK.get_or_init(|| {
+        Regex::new(r"(?i)<br\s*/?>|</(?:p|div|li|h[1-6]|tr)\s*>").unwrap()
+    });
+
+    block
+        .split(html)
+        .map(plain_text)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Render a transcript in the given format, with the extension to save it
+/// under. Transcripts without timings can only be saved as text.
+fn render(transcript: &Transcript, format: TranscriptFormat) -> (&'static str, String) {
+    let cues = match (transcript, format) {
+        (Transcript::Timed(cues), TranscriptFormat::Srt | TranscriptFormat::Vtt) => cues,
+        (Transcript::Untimed(_), TranscriptFormat::Srt | TranscriptFormat::Vtt) => {
+            tracing::debug!("Transcript has no timings, saving it as text");
+            return ("txt", format!("{}\n", to_text(transcript)));
+        }
+        (_, TranscriptFormat::Txt) => return ("txt", format!("{}\n", to_text(transcript))),
+    };
+
+    let mut out = String::new();
+    if format == TranscriptFormat::Vtt {
+        out.push_str("WEBVTT\n\n");
+    }
+    let mut previous_speaker = None;
+    for (i, cue) in cues.iter().enumerate() {
+        let text = match format {
+            TranscriptFormat::Vtt => {
+                let text = cue.text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+                match &cue.speaker {
+                    Some(speaker) => format!("<v {}>{}", speaker, text),
+                    None => text,
+                }
+            }
+            // SRT has no speakers, so name them when they change
+            _ => match &cue.speaker {
+                Some(speaker) if previous_speaker != Some(speaker) => format!("{}: {}", speaker, cue.text),
+                _ => cue.text.clone(),
+            },
+        };
+        previous_speaker = cue.speaker.as_ref();
+
+        if format == TranscriptFormat::Srt {
+            out.push_str(&format!("{}\n", i + 1));
+        }
+        let separator = if format == TranscriptFormat::Srt { ',' } else { '.' };
+        out.push_str(&format!(
+            "{} --> {}\n{}\n\n",
+            format_timestamp(cue.start_ms, separator),
+            format_timestamp(cue.end_ms, separator),
+            text
+        ));
+    }
+    let extension = if format == TranscriptFormat::Srt { "srt" } else { "vtt" };
+    (extension, out)
+}
+
+/// A transcript as plain text. Consecutive cues from the same speaker form
+/// a paragraph; cues without a speaker get a line each.
+fn to_text(transcript: &Transcript) -> String {
+    let cues = match transcript {
+        Transcript::Timed(cues) => cues,
+        Transcript::Untimed(text) => return text.clone(),
+    };
+
+    let mut text = String::new();
+    let mut current_speaker: Option<&str> = None;
+    for cue in cues {
+        let cue_text = cue.text.replace('\n', " ");
+        match cue.speaker.as_deref() {
+            Some(speaker) if current_speaker == Some(speaker) => {
+                text.push(' ');
+                text.push_str(&cue_text);
+            }
+            Some(speaker) => {
+                if !text.is_empty() {
+                    text.push_str("\n\n");
+                }
+                text.push_str(&format!("{}: {}", speaker, cue_text));
+            }
+            None => {
+                if !text.is_empty() {
+                    text.push('\n');
+                }
+                text.push_str(&cue_text);
+            }
+        }
+        current_speaker = cue.speaker.as_deref();
+    }
+    text
+}
+
+fn format_timestamp(millis: u64, separator: char) -> String {
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        separator,
+        millis % 1000
+    )
+}
+
+fn seconds_to_millis(seconds: f64) -> u64 {
+    (seconds.max(0.0) * 1000.0).round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cue(start_ms: u64, end_ms: u64, speaker: Option<&str>, text: &str) -> Cue {
+        Cue {
+            start_ms,
+            end_ms,
+            speaker: speaker.map(str::to_string),
+            text: text.to_string(),
+        }
+    }
+
+    fn summary(cues: &[Cue]) -> Vec<(u64, u64, Option<&str>, &str)> {
+        cues.iter()
+            .map(|cue| (cue.start_ms, cue.end_ms, cue.speaker.as_deref(), cue.text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_timestamp("00:01:02,345"), Some(62_345));
+        assert_eq!(parse_timestamp(" 01:02:03.004 "), Some(3_723_004));
+        assert_eq!(parse_timestamp("01:02.5"), Some(62_500));
+        assert_eq!(parse_timestamp("01:02"), None);
+        assert_eq!(parse_timestamp("1:2:3:4.000"), None);
+        assert_eq!(parse_timestamp("aa:bb.ccc"), None);
+    }
+
+    #[test]
+    fn parses_srt_cues() {
+        let srt = "1\r\n00:00:01,000 --> 00:00:02,500\r\n<i>Hello</i> there\r\n\r\n\
+                   2\r\n00:00:03,000 --> 00:00:04,000\r\n<font color=\"red\">Second</font> &amp; last\r\n";
+        let cues = parse_cues(srt);
+        assert_eq!(
+            summary(&cues),
+            [(1000, 2500, None, "Hello there"), (3000, 4000, None, "Second & last")]
+        );
+    }
+
+    #[test]
+    fn parses_vtt_cues_with_voices_and_settings() {
+        let vtt = "WEBVTT\n\nNOTE not a cue\n\n\
+                   intro\n00:01.000 --> 00:02.000 align:start position:10%\n<v.loud Ann>Hi <b>the</b>re\n\n\
+                   00:02.000 --> 00:03.000\n<v Bob>Two <00:02.500>words</v>\n<c.yellow>second</c> line\n";
+        let cues = parse_cues(vtt);
+        assert_eq!(
+            summary(&cues),
+            [
+                (1000, 2000, Some("Ann"), "Hi there"),
+                (2000, 3000, Some("Bob"), "Two words\nsecond line"),
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_srt_and_vtt() {
+        let transcript = Transcript::Timed(vec![
+            cue(0, 1500, Some("Ann"), "Hi & <bye>"),
+            cue(1500, 3000, Some("Ann"), "Again"),
+            cue(3_600_001, 3_600_002, Some("Bob"), "Later"),
+        ]);
+
+        let (extension, srt) = render(&transcript, TranscriptFormat::Srt);
+        assert_eq!(extension, "srt");
+        assert_eq!(
+            srt,
+            "1\n00:00:00,000 --> 00:00:01,500\nAnn: Hi & <bye>\n\n\
+             2\n00:00:01,500 --> 00:00:03,000\nAgain\n\n\
+             3\n01:00:00,001 --> 01:00:00,002\nBob: Later\n\n"
+        );
+
+        let (extension, vtt) = render(&transcript, TranscriptFormat::Vtt);
+        assert_eq!(extension, "vtt");
+        assert_eq!(
+            vtt,
+            "WEBVTT\n\n00:00:00.000 --> 00:00:01.500\n<v Ann>Hi &amp; &lt;bye&gt;\n\n\
+             00:00:01.500 --> 00:00:03.000\n<v Ann>Again\n\n\
+             01:00:00.001 --> 01:00:00.002\n<v Bob>Later\n\n"
+        );
+        // What was rendered parses back to the same cues
+        assert_eq!(
+            summary(&parse_cues(&vtt)),
+            [
+                (0, 1500, Some("Ann"), "Hi & <bye>"),
+                (1500, 3000, Some("Ann"), "Again"),
+                (3_600_001, 3_600_002, Some("Bob"), "Later"),
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_untimed_transcripts_as_text() {
+        let transcript = Transcript::Untimed("Just text".to_string());
+        assert_eq!(render(&transcript, TranscriptFormat::Vtt), ("txt", "Just text\n".to_string()));
+    }
+
+    #[test]
+    fn text_groups_cues_by_speaker() {
+        let transcript = Transcript::Timed(vec![
+            cue(0, 1000, Some("Ann"), "One"),
+            cue(1000, 2000, Some("Ann"), "two"),
+            cue(2000, 3000, Some("Bob"), "Three"),
+            cue(3000, 4000, None, "Music"),
+        ]);
+        assert_eq!(to_text(&transcript), "Ann: One two\n\nBob: Three\nMusic");
+    }
+}